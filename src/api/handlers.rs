@@ -1,9 +1,23 @@
-use crate::core::document::Document;
+use crate::common::error::EngineError;
+use crate::core::aggregation::{parse_facets, parse_terms, Aggregations};
+use crate::core::bulk;
+use crate::core::document::{Document, Refresh, WriteOptions, WriteResult};
+use crate::core::highlight::HighlightOptions;
+use crate::core::mapping::MappingUpdate;
+use crate::core::query::{SearchOptions, SearchRequest, SearchResults};
 use crate::core::search::SearchEngine;
-use serde_json::json;
+use crate::core::sort::parse_sort;
+use crate::core::suggest::SuggestRequest;
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Instant;
 use warp::filters::BoxedFilter;
+use warp::hyper::body::Bytes;
+use warp::reply::{Json, WithStatus};
 use warp::{Filter, Rejection, Reply};
 
 #[derive(Debug)]
@@ -14,9 +28,17 @@ struct JsonError {
 impl warp::reject::Reject for JsonError {}
 
 pub fn json_body() -> BoxedFilter<(Document,)> {
+    json_payload()
+}
+
+pub fn patch_body() -> BoxedFilter<(Value,)> {
+    json_payload()
+}
+
+fn json_payload<T: DeserializeOwned + Send + 'static>() -> BoxedFilter<(T,)> {
     warp::body::content_length_limit(1024 * 16)
         .and(warp::body::json())
-        .map(|doc: Document| doc)
+        .map(|payload: T| payload)
         .or_else(|rejection: Rejection| async move {
             if let Some(error) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
                 let message = error
@@ -31,44 +53,502 @@ pub fn json_body() -> BoxedFilter<(Document,)> {
         .boxed()
 }
 
+pub fn search_body() -> BoxedFilter<(SearchRequest,)> {
+    json_payload()
+}
+
+pub fn mapping_body() -> BoxedFilter<(MappingUpdate,)> {
+    json_payload()
+}
+
+pub fn bulk_body() -> BoxedFilter<(Bytes,)> {
+    warp::body::content_length_limit(1024 * 1024 * 100)
+        .and(warp::body::bytes())
+        .boxed()
+}
+
 pub async fn handle_add_document(
     doc: Document,
+    params: HashMap<String, String>,
     engine: Arc<SearchEngine>,
 ) -> Result<impl Reply, Rejection> {
-    match engine.add_document(doc).await {
-        Ok(_) => Ok(warp::reply::with_status(
+    let options = match write_options(&params) {
+        Ok(options) => options,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    match engine.add_document_with_options(doc, &options).await {
+        Ok(response) => {
+            let (code, message) = match response.result {
+                WriteResult::Created => (
+                    warp::http::StatusCode::CREATED,
+                    "Document added successfully",
+                ),
+                _ => (warp::http::StatusCode::OK, "Document updated successfully"),
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "status": "success",
+                    "_id": response.id,
+                    "result": response.result,
+                    "_version": response.version,
+                    "_seq_no": response.seq_no,
+                    "message": message
+                })),
+                code,
+            ))
+        }
+        Err(e) => Ok(warp::reply::with_status(
             warp::reply::json(&json!({
-                "status": "success",
-                "message": "Document added successfully"
+                "status": "error",
+                "message": format!("Failed to add document: {}", e)
             })),
-            warp::http::StatusCode::CREATED,
+            error_status(&e),
+        )),
+    }
+}
+
+pub async fn handle_bulk(
+    body: Bytes,
+    params: HashMap<String, String>,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    let started = Instant::now();
+
+    let options = match write_options(&params) {
+        Ok(options) => options,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    let operations = match std::str::from_utf8(&body)
+        .map_err(anyhow::Error::from)
+        .and_then(bulk::parse_ndjson)
+    {
+        Ok(operations) => operations,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "status": "error",
+                    "message": format!("Malformed bulk request: {}", e)
+                })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+
+    match engine.bulk_with_options(operations, &options).await {
+        Ok(items) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "took": started.elapsed().as_millis() as u64,
+                "errors": items.iter().any(|item| item.is_error()),
+                "items": items
+            })),
+            warp::http::StatusCode::OK,
         )),
         Err(e) => Ok(warp::reply::with_status(
             warp::reply::json(&json!({
                 "status": "error",
-                "message": format!("Failed to add document: {}", e)
+                "message": format!("Bulk request failed: {}", e)
             })),
             warp::http::StatusCode::INTERNAL_SERVER_ERROR,
         )),
     }
 }
 
+pub async fn handle_get_document(
+    id: String,
+    params: HashMap<String, String>,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    let id = match document_id(&id) {
+        Ok(id) => id,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    match engine.get_document(&id).await {
+        Some(doc) => {
+            let document = match params.get("fields") {
+                Some(fields) => {
+                    let fields: Vec<&str> = fields
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|field| !field.is_empty())
+                        .collect();
+                    doc.project(&fields)
+                }
+                None => json!(doc),
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "status": "success",
+                    "found": true,
+                    "document": document
+                })),
+                warp::http::StatusCode::OK,
+            ))
+        }
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "error",
+                "found": false,
+                "message": EngineError::DocumentNotFound(id).to_string()
+            })),
+            warp::http::StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+pub async fn handle_head_document(
+    id: String,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    let code = match document_id(&id) {
+        Ok(id) if engine.contains_document(&id).await => warp::http::StatusCode::OK,
+        Ok(_) => warp::http::StatusCode::NOT_FOUND,
+        Err(_) => warp::http::StatusCode::BAD_REQUEST,
+    };
+
+    Ok(warp::reply::with_status(warp::reply(), code))
+}
+
+pub async fn handle_update_document(
+    id: String,
+    patch: Value,
+    params: HashMap<String, String>,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    let id = match document_id(&id) {
+        Ok(id) => id,
+        Err(message) => return Ok(bad_request(message)),
+    };
+    let options = match write_options(&params) {
+        Ok(options) => options,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    match engine
+        .update_document_with_options(&id, &patch, &options)
+        .await
+    {
+        Ok(document) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "success",
+                "result": WriteResult::Updated,
+                "document": document
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "error",
+                "message": format!("Failed to update document: {}", e)
+            })),
+            error_status(&e),
+        )),
+    }
+}
+
+pub async fn handle_delete_document(
+    id: String,
+    params: HashMap<String, String>,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    let id = match document_id(&id) {
+        Ok(id) => id,
+        Err(message) => return Ok(bad_request(message)),
+    };
+    let options = match write_options(&params) {
+        Ok(options) => options,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    match engine.delete_document_with_options(&id, &options).await {
+        Ok(response) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "success",
+                "_id": response.id,
+                "result": response.result,
+                "_version": response.version,
+                "_seq_no": response.seq_no,
+                "message": "Document deleted successfully"
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "error",
+                "message": format!("Failed to delete document: {}", e)
+            })),
+            error_status(&e),
+        )),
+    }
+}
+
 pub async fn handle_search(
-    params: std::collections::HashMap<String, String>,
+    params: HashMap<String, String>,
     engine: Arc<SearchEngine>,
 ) -> Result<impl Reply, Rejection> {
     let query = params.get("q").cloned().unwrap_or_default();
+    let options = match search_options(&params) {
+        Ok(options) => options,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    Ok(search_reply(
+        engine.search_with_options(&query, &options).await,
+    ))
+}
+
+pub async fn handle_search_dsl(
+    request: SearchRequest,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    Ok(search_reply(
+        engine.search_dsl(&request.query, &request.options).await,
+    ))
+}
+
+pub async fn handle_suggest(
+    params: HashMap<String, String>,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    let started = Instant::now();
+    let request = match suggest_request(&params) {
+        Ok(request) => request,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    match engine.suggest(&request).await {
+        Ok(suggestions) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "success",
+                "took": started.elapsed().as_millis() as u64,
+                "prefix": request.prefix,
+                "suggestions": suggestions
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "error",
+                "message": format!("Suggest failed: {}", e)
+            })),
+            error_status(&e),
+        )),
+    }
+}
+
+fn suggest_request(params: &HashMap<String, String>) -> Result<SuggestRequest, String> {
+    let defaults = SuggestRequest::default();
+    let prefix = params
+        .get("prefix")
+        .cloned()
+        .ok_or_else(|| "Missing prefix parameter".to_string())?;
+    let size = match params.get("size") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid size '{}'", value))?,
+        None => defaults.size,
+    };
+    let fields = match params.get("fields") {
+        Some(fields) => fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(String::from)
+            .collect(),
+        None => defaults.fields,
+    };
+
+    Ok(SuggestRequest {
+        prefix,
+        size,
+        fields,
+        category: params.get("category").cloned(),
+    })
+}
+
+fn search_reply(results: anyhow::Result<SearchResults>) -> WithStatus<Json> {
+    match results {
+        Ok(results) => warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "success",
+                "total": results.total,
+                "max_score": results.max_score,
+                "count": results.hits.len(),
+                "results": results.hits,
+                "search_after": results.search_after,
+                "aggregations": results.aggregations,
+                "suggestions": results.suggestions
+            })),
+            warp::http::StatusCode::OK,
+        ),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "error",
+                "message": format!("Search failed: {}", e)
+            })),
+            match e.downcast_ref::<EngineError>() {
+                Some(_) => error_status(&e),
+                None => warp::http::StatusCode::OK,
+            },
+        ),
+    }
+}
+
+fn search_options(params: &HashMap<String, String>) -> Result<SearchOptions, String> {
+    let number = |name: &str, default: usize| {
+        params
+            .get(name)
+            .map(|value| {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid {} '{}'", name, value))
+            })
+            .unwrap_or(Ok(default))
+    };
 
-    match engine.search(&query).await {
-        Ok(results) => Ok(warp::reply::json(&json!({
-            "status": "success",
-            "count": results.len(),
-            "results": results
-        }))),
-        Err(e) => Ok(warp::reply::json(&json!({
+    let min_score = params
+        .get("min_score")
+        .map(|value| {
+            value
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid min_score '{}'", value))
+        })
+        .transpose()?;
+
+    let defaults = SearchOptions::default();
+    let search_after = params
+        .get("search_after")
+        .map(|value| value.parse().map_err(|e: EngineError| e.to_string()))
+        .transpose()?;
+
+    let highlight = match params.get("highlight") {
+        Some(fields) => {
+            let defaults = HighlightOptions::default();
+            Some(HighlightOptions {
+                fields: fields
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|field| !field.is_empty())
+                    .map(String::from)
+                    .collect(),
+                fragment_size: number("fragment_size", defaults.fragment_size)?,
+                number_of_fragments: number("number_of_fragments", defaults.number_of_fragments)?,
+                pre_tag: params.get("pre_tag").cloned().unwrap_or(defaults.pre_tag),
+                post_tag: params.get("post_tag").cloned().unwrap_or(defaults.post_tag),
+            })
+        }
+        None => None,
+    };
+
+    let suggest = match params.get("suggest") {
+        Some(value) => value
+            .parse::<bool>()
+            .map_err(|_| format!("Invalid suggest '{}'", value))?,
+        None => defaults.suggest,
+    };
+
+    let mut aggs = match params.get("aggs") {
+        Some(spec) if spec.trim_start().starts_with('{') => {
+            serde_json::from_str(spec).map_err(|e| format!("Invalid aggs: {}", e))?
+        }
+        Some(spec) => parse_terms(spec).map_err(|e| e.to_string())?,
+        None => Aggregations::new(),
+    };
+    if let Some(spec) = params.get("facet") {
+        aggs.extend(parse_facets(spec).map_err(|e| e.to_string())?);
+    }
+
+    Ok(SearchOptions {
+        from: number("from", defaults.from)?,
+        size: number("size", defaults.size)?,
+        search_after,
+        min_score,
+        highlight,
+        sort: match params.get("sort") {
+            Some(spec) => parse_sort(spec).map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        },
+        aggs,
+        fuzzy_prefix_length: number("fuzzy_prefix_length", defaults.fuzzy_prefix_length)?,
+        suggest,
+    })
+}
+
+pub async fn handle_get_mapping(engine: Arc<SearchEngine>) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&json!({
+        "status": "success",
+        "mapping": engine.mapping().view()
+    })))
+}
+
+pub async fn handle_put_mapping(
+    update: MappingUpdate,
+    engine: Arc<SearchEngine>,
+) -> Result<impl Reply, Rejection> {
+    match engine.put_mapping(update).await {
+        Ok(mapping) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "success",
+                "mapping": mapping.view()
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "error",
+                "message": format!("Failed to update mapping: {}", e)
+            })),
+            error_status(&e),
+        )),
+    }
+}
+
+fn document_id(id: &str) -> Result<String, String> {
+    percent_decode_str(id)
+        .decode_utf8()
+        .map(|id| id.into_owned())
+        .map_err(|_| format!("Invalid document id '{}'", id))
+}
+
+fn write_options(params: &HashMap<String, String>) -> Result<WriteOptions, String> {
+    let if_version = params
+        .get("if_version")
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid if_version '{}'", value))
+        })
+        .transpose()?;
+
+    let refresh = match params.get("refresh").map(String::as_str) {
+        None | Some("false") => Refresh::False,
+        Some("") | Some("true") => Refresh::True,
+        Some("wait_for") => Refresh::WaitFor,
+        Some(value) => return Err(format!("Invalid refresh '{}'", value)),
+    };
+
+    Ok(WriteOptions {
+        if_version,
+        refresh,
+    })
+}
+
+fn bad_request(message: String) -> WithStatus<Json> {
+    warp::reply::with_status(
+        warp::reply::json(&json!({
             "status": "error",
-            "message": format!("Search failed: {}", e)
-        }))),
+            "message": message
+        })),
+        warp::http::StatusCode::BAD_REQUEST,
+    )
+}
+
+fn error_status(error: &anyhow::Error) -> warp::http::StatusCode {
+    match error.downcast_ref::<EngineError>() {
+        Some(e) => warp::http::StatusCode::from_u16(e.status_code()).unwrap(),
+        None => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
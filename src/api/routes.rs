@@ -12,13 +12,81 @@ pub fn search_routes(
         .and(with_engine(engine.clone()))
         .and_then(handlers::handle_search);
 
+    let search_dsl = warp::path("search")
+        .and(warp::post())
+        .and(handlers::search_body())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_search_dsl);
+
+    let suggest = warp::path("suggest")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_suggest);
+
     let add = warp::path("documents")
+        .and(warp::path::end())
         .and(warp::post())
         .and(handlers::json_body())
-        .and(with_engine(engine))
+        .and(warp::query())
+        .and(with_engine(engine.clone()))
         .and_then(handlers::handle_add_document);
 
-    search.or(add).recover(handlers::handle_rejection)
+    let bulk = warp::path!("documents" / "_bulk")
+        .and(warp::post())
+        .and(handlers::bulk_body())
+        .and(warp::query())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_bulk);
+
+    let get = warp::path!("documents" / String)
+        .and(warp::get())
+        .and(warp::query())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_get_document);
+
+    let head = warp::path!("documents" / String)
+        .and(warp::head())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_head_document);
+
+    let update = warp::path!("documents" / String / "_update")
+        .and(warp::post())
+        .and(handlers::patch_body())
+        .and(warp::query())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_update_document);
+
+    let delete = warp::path!("documents" / String)
+        .and(warp::delete())
+        .and(warp::query())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_delete_document);
+
+    let get_mapping = warp::path!("_mapping")
+        .and(warp::get())
+        .and(with_engine(engine.clone()))
+        .and_then(handlers::handle_get_mapping);
+
+    let put_mapping = warp::path!("_mapping")
+        .and(warp::put())
+        .and(handlers::mapping_body())
+        .and(with_engine(engine))
+        .and_then(handlers::handle_put_mapping);
+
+    search
+        .or(search_dsl)
+        .or(suggest)
+        .or(add)
+        .or(bulk)
+        .or(get)
+        .or(head)
+        .or(update)
+        .or(delete)
+        .or(get_mapping)
+        .or(put_mapping)
+        .recover(handlers::handle_rejection)
 }
 
 fn with_engine(
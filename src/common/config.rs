@@ -1,6 +1,8 @@
 use config::{Config as ConfigLib, Environment, File};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::IpAddr;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
@@ -14,10 +16,62 @@ pub struct StorageConfig {
     pub index_path: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexingConfig {
+    pub refresh_interval: String,
+    pub max_buffered_docs: usize,
+    pub analyzer: String,
+    pub field_analyzers: BTreeMap<String, String>,
+}
+
+impl Default for IndexingConfig {
+    fn default() -> Self {
+        IndexingConfig {
+            refresh_interval: "1s".to_string(),
+            max_buffered_docs: 10_000,
+            analyzer: "default".to_string(),
+            field_analyzers: BTreeMap::new(),
+        }
+    }
+}
+
+impl IndexingConfig {
+    pub fn refresh_interval(&self) -> anyhow::Result<Option<Duration>> {
+        let value = self.refresh_interval.trim();
+        if value == "-1" {
+            return Ok(None);
+        }
+
+        let (number, unit) = value
+            .find(|c: char| !c.is_ascii_digit())
+            .map(|pos| value.split_at(pos))
+            .unwrap_or((value, "ms"));
+        let number: u64 = number
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid refresh_interval '{}'", value))?;
+
+        let interval = match unit {
+            "ms" => Duration::from_millis(number),
+            "s" => Duration::from_secs(number),
+            "m" => Duration::from_secs(number * 60),
+            _ => anyhow::bail!("Invalid refresh_interval unit in '{}'", value),
+        };
+
+        if interval.is_zero() {
+            anyhow::bail!("refresh_interval must be positive or -1");
+        }
+
+        Ok(Some(interval))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub storage: StorageConfig,
+    #[serde(default)]
+    pub indexing: IndexingConfig,
 }
 
 impl Config {
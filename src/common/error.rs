@@ -1,5 +1,6 @@
 use anyhow::Error as AnyhowError;
 use std::fmt;
+use thiserror::Error;
 use warp::reject;
 
 #[derive(Debug)]
@@ -18,3 +19,33 @@ impl From<AnyhowError> for StorageError {
         StorageError(err)
     }
 }
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("Document not found: {0}")]
+    DocumentNotFound(String),
+    #[error("Invalid document: {0}")]
+    InvalidDocument(String),
+    #[error("Invalid mapping: {0}")]
+    InvalidMapping(String),
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("Version conflict for document {id}: expected version {expected}, current version {current}")]
+    VersionConflict {
+        id: String,
+        expected: u64,
+        current: u64,
+    },
+}
+
+impl EngineError {
+    pub fn status_code(&self) -> u16 {
+        match self {
+            EngineError::DocumentNotFound(_) => 404,
+            EngineError::InvalidDocument(_)
+            | EngineError::InvalidMapping(_)
+            | EngineError::InvalidQuery(_) => 400,
+            EngineError::VersionConflict { .. } => 409,
+        }
+    }
+}
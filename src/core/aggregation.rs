@@ -0,0 +1,1338 @@
+use super::date_math;
+use super::mapping::keyword_field;
+use super::value::date_from_millis;
+use crate::common::error::EngineError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::fastfield::{FacetReader, FastValue, MultiValuedFastFieldReader};
+use tantivy::schema::{Facet, Field, FieldType, Schema};
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::time::Month;
+use tantivy::{
+    f64_to_u64, u64_to_f64, DateTime, DocId, InvertedIndexReader, Score, SegmentOrdinal,
+    SegmentReader,
+};
+
+pub const DEFAULT_BUCKETS: usize = 10;
+pub const MAX_BUCKETS: usize = 10_000;
+pub const DEFAULT_PERCENTS: [f64; 7] = [1.0, 5.0, 25.0, 50.0, 75.0, 95.0, 99.0];
+
+const SKETCH_PRECISION: u32 = 12;
+const DIGEST_COMPRESSION: f64 = 100.0;
+const DIGEST_BUFFER: usize = 512;
+
+pub type Aggregations = BTreeMap<String, Aggregation>;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Aggregation {
+    #[serde(flatten)]
+    pub kind: AggregationKind,
+    #[serde(default, alias = "aggregations")]
+    pub aggs: Aggregations,
+}
+
+impl Aggregation {
+    pub fn new(kind: AggregationKind) -> Self {
+        Aggregation {
+            kind,
+            aggs: Aggregations::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregationKind {
+    Terms(TermsAggregation),
+    Facet(FacetAggregation),
+    Histogram(HistogramAggregation),
+    DateHistogram(DateHistogramAggregation),
+    Min(FieldAggregation),
+    Max(FieldAggregation),
+    Avg(FieldAggregation),
+    Sum(FieldAggregation),
+    Stats(FieldAggregation),
+    Percentiles(PercentilesAggregation),
+    Cardinality(FieldAggregation),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TermsAggregation {
+    pub field: String,
+    #[serde(default = "default_buckets")]
+    pub size: usize,
+}
+
+fn default_buckets() -> usize {
+    DEFAULT_BUCKETS
+}
+
+impl TermsAggregation {
+    pub fn new(field: &str, size: usize) -> Self {
+        TermsAggregation {
+            field: field.to_string(),
+            size,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FacetAggregation {
+    pub field: String,
+    #[serde(default = "default_facet_path")]
+    pub path: String,
+    #[serde(default = "default_buckets")]
+    pub size: usize,
+}
+
+fn default_facet_path() -> String {
+    "/".to_string()
+}
+
+impl FacetAggregation {
+    pub fn new(field: &str, path: &str, size: usize) -> Self {
+        FacetAggregation {
+            field: field.to_string(),
+            path: path.to_string(),
+            size,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistogramAggregation {
+    pub field: String,
+    pub interval: f64,
+    #[serde(default)]
+    pub min_doc_count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DateHistogramAggregation {
+    pub field: String,
+    pub calendar_interval: Option<String>,
+    pub fixed_interval: Option<String>,
+    #[serde(default)]
+    pub min_doc_count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldAggregation {
+    pub field: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PercentilesAggregation {
+    pub field: String,
+    #[serde(default = "default_percents")]
+    pub percents: Vec<f64>,
+}
+
+fn default_percents() -> Vec<f64> {
+    DEFAULT_PERCENTS.to_vec()
+}
+
+pub fn parse_terms(spec: &str) -> Result<Aggregations, EngineError> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (field, size) = match part.rsplit_once(':') {
+                Some((field, size)) => {
+                    let size = size.parse().map_err(|_| {
+                        EngineError::InvalidQuery(format!(
+                            "invalid aggregation size '{}' for '{}'",
+                            size, field
+                        ))
+                    })?;
+                    (field, size)
+                }
+                None => (part, DEFAULT_BUCKETS),
+            };
+            Ok((
+                field.to_string(),
+                Aggregation::new(AggregationKind::Terms(TermsAggregation::new(field, size))),
+            ))
+        })
+        .collect()
+}
+
+pub fn parse_facets(spec: &str) -> Result<Aggregations, EngineError> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (field, path) = part.split_once(':').unwrap_or((part, "/"));
+            Facet::from_text(path).map_err(|_| {
+                EngineError::InvalidQuery(format!("invalid facet path '{}' for '{}'", path, field))
+            })?;
+            Ok((
+                field.to_string(),
+                Aggregation::new(AggregationKind::Facet(FacetAggregation::new(
+                    field,
+                    path,
+                    DEFAULT_BUCKETS,
+                ))),
+            ))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum AggregationResult {
+    Buckets(BucketsResult),
+    Value(ValueResult),
+    Stats(StatsResult),
+    Percentiles(PercentilesResult),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BucketsResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sum_other_doc_count: Option<u64>,
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Bucket {
+    pub key: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_as_string: Option<String>,
+    pub doc_count: u64,
+    #[serde(flatten)]
+    pub aggregations: BTreeMap<String, AggregationResult>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ValueResult {
+    pub value: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_as_string: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatsResult {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+    pub sum: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PercentilesResult {
+    pub values: BTreeMap<String, Option<f64>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum BucketKey {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(u64),
+    Date(i64),
+    Str(String),
+}
+
+impl BucketKey {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            BucketKey::Bool(value) => Some(*value as u8 as f64),
+            BucketKey::I64(value) | BucketKey::Date(value) => Some(*value as f64),
+            BucketKey::U64(value) => Some(*value as f64),
+            BucketKey::F64(value) => Some(u64_to_f64(*value)),
+            BucketKey::Str(_) => None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            BucketKey::Bool(value) => Value::from(*value),
+            BucketKey::I64(value) | BucketKey::Date(value) => Value::from(*value),
+            BucketKey::U64(value) => Value::from(*value),
+            BucketKey::F64(value) => Value::from(u64_to_f64(*value)),
+            BucketKey::Str(value) => Value::from(value.as_str()),
+        }
+    }
+
+    fn to_string_key(&self) -> Option<String> {
+        match self {
+            BucketKey::Date(millis) => format_millis(*millis),
+            _ => None,
+        }
+    }
+}
+
+fn format_millis(millis: i64) -> Option<String> {
+    date_from_millis(millis)?.into_utc().format(&Rfc3339).ok()
+}
+
+#[derive(Clone, Copy, Debug)]
+enum FieldSource {
+    Bool(Field),
+    I64(Field),
+    U64(Field),
+    F64(Field),
+    Date(Field),
+    Keyword(Field),
+    Facet(Field),
+}
+
+impl FieldSource {
+    fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            FieldSource::I64(_) | FieldSource::U64(_) | FieldSource::F64(_)
+        )
+    }
+
+    fn is_date(self) -> bool {
+        matches!(self, FieldSource::Date(_))
+    }
+
+    fn is_facet(self) -> bool {
+        matches!(self, FieldSource::Facet(_))
+    }
+}
+
+fn source(schema: &Schema, name: &str) -> Result<FieldSource, EngineError> {
+    let not_aggregatable = || {
+        EngineError::InvalidQuery(format!(
+            "field '{}' is not aggregatable, aggregate on a fast keyword, numeric, boolean, date or facet field",
+            name
+        ))
+    };
+    if name.starts_with('_') {
+        return Err(not_aggregatable());
+    }
+    let field = schema
+        .get_field(&keyword_field(name))
+        .or_else(|| schema.get_field(name))
+        .ok_or_else(not_aggregatable)?;
+    let entry = schema.get_field_entry(field);
+    if !entry.is_fast() {
+        return Err(not_aggregatable());
+    }
+
+    match entry.field_type() {
+        FieldType::Bool(_) => Ok(FieldSource::Bool(field)),
+        FieldType::I64(_) => Ok(FieldSource::I64(field)),
+        FieldType::U64(_) => Ok(FieldSource::U64(field)),
+        FieldType::F64(_) => Ok(FieldSource::F64(field)),
+        FieldType::Date(_) => Ok(FieldSource::Date(field)),
+        FieldType::Str(_) => Ok(FieldSource::Keyword(field)),
+        FieldType::Facet(_) => Ok(FieldSource::Facet(field)),
+        _ => Err(not_aggregatable()),
+    }
+}
+
+enum FieldColumn {
+    Bool(MultiValuedFastFieldReader<bool>),
+    I64(MultiValuedFastFieldReader<i64>),
+    U64(MultiValuedFastFieldReader<u64>),
+    F64(MultiValuedFastFieldReader<f64>),
+    Date(MultiValuedFastFieldReader<DateTime>),
+    Keyword(MultiValuedFastFieldReader<u64>, Arc<InvertedIndexReader>),
+    Facet(FacetReader),
+}
+
+impl FieldColumn {
+    fn open(source: FieldSource, segment_reader: &SegmentReader) -> tantivy::Result<Self> {
+        let fast_fields = segment_reader.fast_fields();
+        Ok(match source {
+            FieldSource::Bool(field) => FieldColumn::Bool(fast_fields.bools(field)?),
+            FieldSource::I64(field) => FieldColumn::I64(fast_fields.i64s(field)?),
+            FieldSource::U64(field) => FieldColumn::U64(fast_fields.u64s(field)?),
+            FieldSource::F64(field) => FieldColumn::F64(fast_fields.f64s(field)?),
+            FieldSource::Date(field) => FieldColumn::Date(fast_fields.dates(field)?),
+            FieldSource::Keyword(field) => FieldColumn::Keyword(
+                fast_fields.u64s(field)?,
+                segment_reader.inverted_index(field)?,
+            ),
+            FieldSource::Facet(field) => FieldColumn::Facet(segment_reader.facet_reader(field)?),
+        })
+    }
+
+    fn read(&self, doc: DocId) -> Vec<BucketKey> {
+        match self {
+            FieldColumn::Bool(reader) => read_values(reader, doc, BucketKey::Bool),
+            FieldColumn::I64(reader) => read_values(reader, doc, BucketKey::I64),
+            FieldColumn::U64(reader) => read_values(reader, doc, BucketKey::U64),
+            FieldColumn::F64(reader) => {
+                read_values(reader, doc, |value| BucketKey::F64(f64_to_u64(value)))
+            }
+            FieldColumn::Date(reader) => read_values(reader, doc, |date| {
+                BucketKey::Date(date.into_timestamp_millis())
+            }),
+            FieldColumn::Keyword(reader, _) => read_values(reader, doc, BucketKey::U64),
+            FieldColumn::Facet(reader) => {
+                let mut ords = Vec::new();
+                reader.facet_ords(doc, &mut ords);
+                ords.into_iter().map(BucketKey::U64).collect()
+            }
+        }
+    }
+
+    fn resolve(&self, key: BucketKey) -> Option<BucketKey> {
+        match (self, key) {
+            (FieldColumn::Keyword(_, inverted_index), BucketKey::U64(ord)) => {
+                let mut bytes = Vec::new();
+                inverted_index
+                    .terms()
+                    .ord_to_term(ord, &mut bytes)
+                    .unwrap_or(false)
+                    .then(|| BucketKey::Str(String::from_utf8_lossy(&bytes).into_owned()))
+            }
+            (FieldColumn::Facet(reader), BucketKey::U64(ord)) => {
+                let mut bytes = Vec::new();
+                reader
+                    .facet_dict()
+                    .ord_to_term(ord, &mut bytes)
+                    .unwrap_or(false)
+                    .then(|| Facet::from_encoded(bytes).ok())
+                    .flatten()
+                    .map(|facet| BucketKey::Str(facet.to_string()))
+            }
+            (_, key) => Some(key),
+        }
+    }
+
+    fn facet_children(&self, path: &Facet) -> tantivy::Result<HashMap<u64, u64>> {
+        let FieldColumn::Facet(reader) = self else {
+            return Ok(HashMap::new());
+        };
+        let encoded = path.encoded_str();
+        let (prefix, depth) = if encoded.is_empty() {
+            (Vec::new(), 0)
+        } else {
+            (
+                format!("{}\u{0}", encoded).into_bytes(),
+                facet_depth(encoded.as_bytes()),
+            )
+        };
+
+        let mut children = HashMap::new();
+        let mut child = None;
+        let mut stream = reader.facet_dict().range().ge(&prefix).into_stream()?;
+        while stream.advance() {
+            let key = stream.key();
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            if key.is_empty() {
+                continue;
+            }
+            if facet_depth(key) == depth + 1 {
+                child = Some(stream.term_ord());
+            }
+            if let Some(child) = child {
+                children.insert(stream.term_ord(), child);
+            }
+        }
+        Ok(children)
+    }
+}
+
+fn facet_depth(encoded: &[u8]) -> usize {
+    if encoded.is_empty() {
+        0
+    } else {
+        encoded.iter().filter(|byte| **byte == 0).count() + 1
+    }
+}
+
+fn read_values<T: FastValue>(
+    reader: &MultiValuedFastFieldReader<T>,
+    doc: DocId,
+    key: impl Fn(T) -> BucketKey,
+) -> Vec<BucketKey> {
+    let mut values = Vec::new();
+    reader.get_vals(doc, &mut values);
+    values.into_iter().map(key).collect()
+}
+
+#[derive(Clone, Copy, Debug)]
+enum DateInterval {
+    Fixed(i64),
+    Calendar(char),
+}
+
+impl DateInterval {
+    fn parse(name: &str, aggregation: &DateHistogramAggregation) -> Result<Self, EngineError> {
+        let invalid = |interval: &str| {
+            EngineError::InvalidQuery(format!(
+                "invalid interval '{}' for date_histogram '{}'",
+                interval, name
+            ))
+        };
+
+        match (&aggregation.calendar_interval, &aggregation.fixed_interval) {
+            (Some(interval), None) => {
+                let unit = match interval.as_str() {
+                    "minute" | "1m" => 'm',
+                    "hour" | "1h" => 'h',
+                    "day" | "1d" => 'd',
+                    "week" | "1w" => 'w',
+                    "month" | "1M" => 'M',
+                    "quarter" | "1q" => 'q',
+                    "year" | "1y" => 'y',
+                    _ => return Err(invalid(interval)),
+                };
+                Ok(DateInterval::Calendar(unit))
+            }
+            (None, Some(interval)) => {
+                let digits = interval
+                    .find(|c: char| !c.is_ascii_digit())
+                    .ok_or_else(|| invalid(interval))?;
+                let amount: i64 = interval[..digits].parse().map_err(|_| invalid(interval))?;
+                let unit = match &interval[digits..] {
+                    "ms" => 1,
+                    "s" => 1_000,
+                    "m" => 60_000,
+                    "h" => 3_600_000,
+                    "d" => 86_400_000,
+                    _ => return Err(invalid(interval)),
+                };
+                match amount.checked_mul(unit) {
+                    Some(millis) if millis > 0 => Ok(DateInterval::Fixed(millis)),
+                    _ => Err(invalid(interval)),
+                }
+            }
+            _ => Err(EngineError::InvalidQuery(format!(
+                "date_histogram '{}' needs exactly one of calendar_interval or fixed_interval",
+                name
+            ))),
+        }
+    }
+
+    fn floor(self, millis: i64) -> Option<i64> {
+        match self {
+            DateInterval::Fixed(interval) => millis.div_euclid(interval).checked_mul(interval),
+            DateInterval::Calendar(unit) => {
+                let date = date_from_millis(millis)?.into_utc();
+                let date = match unit {
+                    'q' => {
+                        let month = date_math::round_down(date, 'M')?;
+                        let quarter = (month.month() as u8 - 1) / 3 * 3 + 1;
+                        month.replace_month(Month::try_from(quarter).ok()?).ok()?
+                    }
+                    unit => date_math::round_down(date, unit)?,
+                };
+                Some(DateTime::from_utc(date).into_timestamp_millis())
+            }
+        }
+    }
+
+    fn next(self, millis: i64) -> Option<i64> {
+        match self {
+            DateInterval::Fixed(interval) => millis.checked_add(interval),
+            DateInterval::Calendar(unit) => {
+                let date = date_from_millis(millis)?.into_utc();
+                let date = match unit {
+                    'q' => date_math::shift(date, 3, 'M')?,
+                    unit => date_math::shift(date, 1, unit)?,
+                };
+                Some(DateTime::from_utc(date).into_timestamp_millis())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Metric {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Stats,
+}
+
+#[derive(Clone, Debug)]
+enum PlanKind {
+    Terms {
+        size: usize,
+    },
+    Facet {
+        path: Facet,
+        size: usize,
+    },
+    Histogram {
+        interval: f64,
+        min_doc_count: u64,
+    },
+    DateHistogram {
+        interval: DateInterval,
+        min_doc_count: u64,
+    },
+    Metric(Metric),
+    Percentiles(Vec<f64>),
+    Cardinality,
+}
+
+#[derive(Clone, Debug)]
+struct Plan {
+    name: String,
+    source: FieldSource,
+    kind: PlanKind,
+    children: Vec<Plan>,
+}
+
+impl Plan {
+    fn new(schema: &Schema, name: &str, aggregation: &Aggregation) -> Result<Self, EngineError> {
+        let invalid = |message: &str| {
+            EngineError::InvalidQuery(format!("aggregation '{}' {}", name, message))
+        };
+        let numeric_or_date = |field: &str| {
+            let source = source(schema, field)?;
+            if source.is_numeric() || source.is_date() {
+                Ok(source)
+            } else {
+                Err(invalid("needs a numeric or date field"))
+            }
+        };
+
+        let (source, kind) = match &aggregation.kind {
+            AggregationKind::Terms(terms) => {
+                if terms.size == 0 {
+                    return Err(invalid("size must be greater than 0"));
+                }
+                (
+                    source(schema, &terms.field)?,
+                    PlanKind::Terms { size: terms.size },
+                )
+            }
+            AggregationKind::Facet(facet) => {
+                if facet.size == 0 {
+                    return Err(invalid("size must be greater than 0"));
+                }
+                let source = source(schema, &facet.field)?;
+                if !source.is_facet() {
+                    return Err(invalid("needs a facet field"));
+                }
+                let path = Facet::from_text(&facet.path)
+                    .map_err(|_| invalid(&format!("has an invalid path '{}'", facet.path)))?;
+                (
+                    source,
+                    PlanKind::Facet {
+                        path,
+                        size: facet.size,
+                    },
+                )
+            }
+            AggregationKind::Histogram(histogram) => {
+                if !histogram.interval.is_finite() || histogram.interval <= 0.0 {
+                    return Err(invalid("interval must be a positive number"));
+                }
+                let source = source(schema, &histogram.field)?;
+                if !source.is_numeric() {
+                    return Err(invalid("needs a numeric field"));
+                }
+                (
+                    source,
+                    PlanKind::Histogram {
+                        interval: histogram.interval,
+                        min_doc_count: histogram.min_doc_count,
+                    },
+                )
+            }
+            AggregationKind::DateHistogram(histogram) => {
+                let source = source(schema, &histogram.field)?;
+                if !source.is_date() {
+                    return Err(invalid("needs a date field"));
+                }
+                (
+                    source,
+                    PlanKind::DateHistogram {
+                        interval: DateInterval::parse(name, histogram)?,
+                        min_doc_count: histogram.min_doc_count,
+                    },
+                )
+            }
+            AggregationKind::Min(metric) => (
+                numeric_or_date(&metric.field)?,
+                PlanKind::Metric(Metric::Min),
+            ),
+            AggregationKind::Max(metric) => (
+                numeric_or_date(&metric.field)?,
+                PlanKind::Metric(Metric::Max),
+            ),
+            AggregationKind::Avg(metric) => (
+                numeric_or_date(&metric.field)?,
+                PlanKind::Metric(Metric::Avg),
+            ),
+            AggregationKind::Sum(metric) => (
+                numeric_or_date(&metric.field)?,
+                PlanKind::Metric(Metric::Sum),
+            ),
+            AggregationKind::Stats(metric) => (
+                numeric_or_date(&metric.field)?,
+                PlanKind::Metric(Metric::Stats),
+            ),
+            AggregationKind::Percentiles(percentiles) => {
+                if percentiles
+                    .percents
+                    .iter()
+                    .any(|percent| !(0.0..=100.0).contains(percent))
+                {
+                    return Err(invalid("percents must be between 0 and 100"));
+                }
+                (
+                    numeric_or_date(&percentiles.field)?,
+                    PlanKind::Percentiles(percentiles.percents.clone()),
+                )
+            }
+            AggregationKind::Cardinality(cardinality) => {
+                (source(schema, &cardinality.field)?, PlanKind::Cardinality)
+            }
+        };
+
+        let is_bucketing = matches!(
+            kind,
+            PlanKind::Terms { .. }
+                | PlanKind::Facet { .. }
+                | PlanKind::Histogram { .. }
+                | PlanKind::DateHistogram { .. }
+        );
+        if !is_bucketing && !aggregation.aggs.is_empty() {
+            return Err(invalid("is a metric and cannot have sub-aggregations"));
+        }
+        let children = aggregation
+            .aggs
+            .iter()
+            .map(|(name, aggregation)| Plan::new(schema, name, aggregation))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Plan {
+            name: name.to_string(),
+            source,
+            kind,
+            children,
+        })
+    }
+
+    fn partial(&self) -> Partial {
+        match self.kind {
+            PlanKind::Terms { .. }
+            | PlanKind::Facet { .. }
+            | PlanKind::Histogram { .. }
+            | PlanKind::DateHistogram { .. } => Partial::Buckets(HashMap::new()),
+            PlanKind::Metric(_) => Partial::Stats(StatsPartial::default()),
+            PlanKind::Percentiles(_) => Partial::Digest(Digest::default()),
+            PlanKind::Cardinality => Partial::Sketch(Sketch::new()),
+        }
+    }
+
+    fn empty_bucket(&self) -> BucketPartial {
+        BucketPartial {
+            doc_count: 0,
+            children: self.children.iter().map(Plan::partial).collect(),
+        }
+    }
+
+    fn finish(&self, partial: Partial) -> Result<AggregationResult, EngineError> {
+        match (&self.kind, partial) {
+            (
+                PlanKind::Terms { size } | PlanKind::Facet { size, .. },
+                Partial::Buckets(buckets),
+            ) => {
+                let mut buckets: Vec<_> = buckets.into_iter().collect();
+                buckets.sort_by(|(left_key, left), (right_key, right)| {
+                    right
+                        .doc_count
+                        .cmp(&left.doc_count)
+                        .then_with(|| left_key.cmp(right_key))
+                });
+                let sum_other_doc_count = buckets
+                    .iter()
+                    .skip(*size)
+                    .map(|(_, bucket)| bucket.doc_count)
+                    .sum();
+                Ok(AggregationResult::Buckets(BucketsResult {
+                    sum_other_doc_count: Some(sum_other_doc_count),
+                    buckets: buckets
+                        .into_iter()
+                        .take(*size)
+                        .map(|(key, bucket)| {
+                            self.bucket(key.to_json(), key.to_string_key(), bucket)
+                        })
+                        .collect::<Result<_, _>>()?,
+                }))
+            }
+            (
+                PlanKind::Histogram {
+                    interval,
+                    min_doc_count,
+                },
+                Partial::Buckets(buckets),
+            ) => {
+                let buckets = self.fill(buckets, *min_doc_count, |key| match key {
+                    BucketKey::I64(index) => index.checked_add(1).map(BucketKey::I64),
+                    _ => None,
+                })?;
+                Ok(AggregationResult::Buckets(BucketsResult {
+                    sum_other_doc_count: None,
+                    buckets: buckets
+                        .into_iter()
+                        .map(|(key, bucket)| {
+                            let key = key.as_f64().unwrap_or_default() * interval;
+                            self.bucket(Value::from(key), None, bucket)
+                        })
+                        .collect::<Result<_, _>>()?,
+                }))
+            }
+            (
+                PlanKind::DateHistogram {
+                    interval,
+                    min_doc_count,
+                },
+                Partial::Buckets(buckets),
+            ) => {
+                let buckets = self.fill(buckets, *min_doc_count, |key| match key {
+                    BucketKey::Date(millis) => interval.next(*millis).map(BucketKey::Date),
+                    _ => None,
+                })?;
+                Ok(AggregationResult::Buckets(BucketsResult {
+                    sum_other_doc_count: None,
+                    buckets: buckets
+                        .into_iter()
+                        .map(|(key, bucket)| {
+                            self.bucket(key.to_json(), key.to_string_key(), bucket)
+                        })
+                        .collect::<Result<_, _>>()?,
+                }))
+            }
+            (PlanKind::Metric(metric), Partial::Stats(stats)) => Ok(self.metric(*metric, stats)),
+            (PlanKind::Percentiles(percents), Partial::Digest(mut digest)) => {
+                digest.compress();
+                Ok(AggregationResult::Percentiles(PercentilesResult {
+                    values: percents
+                        .iter()
+                        .map(|percent| (format!("{:?}", percent), digest.quantile(*percent)))
+                        .collect(),
+                }))
+            }
+            (PlanKind::Cardinality, Partial::Sketch(sketch)) => {
+                Ok(AggregationResult::Value(ValueResult {
+                    value: Value::from(sketch.estimate()),
+                    value_as_string: None,
+                }))
+            }
+            _ => Err(EngineError::InvalidQuery(format!(
+                "aggregation '{}' produced mismatched results",
+                self.name
+            ))),
+        }
+    }
+
+    fn fill(
+        &self,
+        buckets: HashMap<BucketKey, BucketPartial>,
+        min_doc_count: u64,
+        next: impl Fn(&BucketKey) -> Option<BucketKey>,
+    ) -> Result<Vec<(BucketKey, BucketPartial)>, EngineError> {
+        let mut buckets: BTreeMap<BucketKey, BucketPartial> = buckets.into_iter().collect();
+        if min_doc_count == 0 {
+            if let (Some(first), Some(last)) = (
+                buckets.keys().next().cloned(),
+                buckets.keys().next_back().cloned(),
+            ) {
+                let mut key = first;
+                while key < last {
+                    key = match next(&key) {
+                        Some(key) => key,
+                        None => break,
+                    };
+                    if buckets.len() >= MAX_BUCKETS {
+                        return Err(EngineError::InvalidQuery(format!(
+                            "aggregation '{}' would create more than {} buckets",
+                            self.name, MAX_BUCKETS
+                        )));
+                    }
+                    buckets
+                        .entry(key.clone())
+                        .or_insert_with(|| self.empty_bucket());
+                }
+            }
+        }
+        Ok(buckets
+            .into_iter()
+            .filter(|(_, bucket)| bucket.doc_count >= min_doc_count)
+            .collect())
+    }
+
+    fn bucket(
+        &self,
+        key: Value,
+        key_as_string: Option<String>,
+        bucket: BucketPartial,
+    ) -> Result<Bucket, EngineError> {
+        Ok(Bucket {
+            key,
+            key_as_string,
+            doc_count: bucket.doc_count,
+            aggregations: self
+                .children
+                .iter()
+                .zip(bucket.children)
+                .map(|(child, partial)| Ok((child.name.clone(), child.finish(partial)?)))
+                .collect::<Result<_, EngineError>>()?,
+        })
+    }
+
+    fn metric(&self, metric: Metric, stats: StatsPartial) -> AggregationResult {
+        let value = match metric {
+            Metric::Min => stats.min(),
+            Metric::Max => stats.max(),
+            Metric::Avg => stats.avg(),
+            Metric::Sum => Some(stats.sum),
+            Metric::Stats => {
+                return AggregationResult::Stats(StatsResult {
+                    count: stats.count,
+                    min: stats.min(),
+                    max: stats.max(),
+                    avg: stats.avg(),
+                    sum: stats.sum,
+                })
+            }
+        };
+        let value_as_string = match metric {
+            Metric::Min | Metric::Max | Metric::Avg if self.source.is_date() => {
+                value.and_then(|value| format_millis(value as i64))
+            }
+            _ => None,
+        };
+        AggregationResult::Value(ValueResult {
+            value: value.map(Value::from).unwrap_or(Value::Null),
+            value_as_string,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Digest {
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<(f64, f64)>,
+}
+
+impl Digest {
+    fn insert(&mut self, value: f64) {
+        self.buffer.push((value, 1.0));
+        if self.buffer.len() >= DIGEST_BUFFER {
+            self.compress();
+        }
+    }
+
+    fn merge(&mut self, mut other: Digest) {
+        self.buffer.append(&mut other.centroids);
+        self.buffer.append(&mut other.buffer);
+        self.compress();
+    }
+
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut points = std::mem::take(&mut self.centroids);
+        points.append(&mut self.buffer);
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let total: f64 = points.iter().map(|(_, weight)| weight).sum();
+        let mut centroids: Vec<(f64, f64)> = Vec::new();
+        let mut before = 0.0;
+        for (mean, weight) in points {
+            if let Some(last) = centroids.last_mut() {
+                let merged = last.1 + weight;
+                let q = (before + merged / 2.0) / total;
+                if merged <= 4.0 * total * q * (1.0 - q) / DIGEST_COMPRESSION {
+                    last.0 += (mean - last.0) * weight / merged;
+                    last.1 = merged;
+                    continue;
+                }
+                before += last.1;
+            }
+            centroids.push((mean, weight));
+        }
+        self.centroids = centroids;
+    }
+
+    fn quantile(&self, percent: f64) -> Option<f64> {
+        let total: f64 = self.centroids.iter().map(|(_, weight)| weight).sum();
+        let rank = percent / 100.0 * (total - 1.0).max(0.0);
+
+        let mut before = 0.0;
+        let mut previous: Option<(f64, f64)> = None;
+        for &(mean, weight) in &self.centroids {
+            let center = before + (weight - 1.0) / 2.0;
+            if rank <= center {
+                return Some(match previous {
+                    Some((previous_center, previous_mean)) => {
+                        let fraction = (rank - previous_center) / (center - previous_center);
+                        previous_mean + (mean - previous_mean) * fraction
+                    }
+                    None => mean,
+                });
+            }
+            previous = Some((center, mean));
+            before += weight;
+        }
+        previous.map(|(_, mean)| mean)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct StatsPartial {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for StatsPartial {
+    fn default() -> Self {
+        StatsPartial {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl StatsPartial {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: StatsPartial) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    fn avg(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Sketch {
+    registers: Vec<u8>,
+}
+
+impl Sketch {
+    fn new() -> Self {
+        Sketch {
+            registers: vec![0; 1 << SKETCH_PRECISION],
+        }
+    }
+
+    fn insert(&mut self, key: &BucketKey) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - SKETCH_PRECISION)) as usize;
+        let rank = ((hash << SKETCH_PRECISION) | (1 << (SKETCH_PRECISION - 1))).leading_zeros() + 1;
+        self.registers[index] = self.registers[index].max(rank as u8);
+    }
+
+    fn merge(&mut self, other: &Sketch) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let buckets = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / buckets);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let estimate = alpha * buckets * buckets / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        if estimate <= 2.5 * buckets && zeros > 0 {
+            (buckets * (buckets / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct BucketPartial {
+    doc_count: u64,
+    children: Vec<Partial>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Partial {
+    Buckets(HashMap<BucketKey, BucketPartial>),
+    Stats(StatsPartial),
+    Digest(Digest),
+    Sketch(Sketch),
+}
+
+impl Partial {
+    fn merge(&mut self, other: Partial) {
+        match (self, other) {
+            (Partial::Buckets(buckets), Partial::Buckets(other)) => {
+                for (key, bucket) in other {
+                    match buckets.entry(key) {
+                        Entry::Vacant(entry) => {
+                            entry.insert(bucket);
+                        }
+                        Entry::Occupied(mut entry) => {
+                            let merged = entry.get_mut();
+                            merged.doc_count += bucket.doc_count;
+                            for (child, other) in merged.children.iter_mut().zip(bucket.children) {
+                                child.merge(other);
+                            }
+                        }
+                    }
+                }
+            }
+            (Partial::Stats(stats), Partial::Stats(other)) => stats.merge(other),
+            (Partial::Digest(digest), Partial::Digest(other)) => digest.merge(other),
+            (Partial::Sketch(sketch), Partial::Sketch(other)) => sketch.merge(&other),
+            _ => {}
+        }
+    }
+}
+
+struct SegmentNode {
+    column: FieldColumn,
+    plan: Plan,
+    facet_children: HashMap<u64, u64>,
+    children: Vec<SegmentNode>,
+}
+
+impl SegmentNode {
+    fn open(plan: &Plan, segment_reader: &SegmentReader) -> tantivy::Result<Self> {
+        let column = FieldColumn::open(plan.source, segment_reader)?;
+        let facet_children = match &plan.kind {
+            PlanKind::Facet { path, .. } => column.facet_children(path)?,
+            _ => HashMap::new(),
+        };
+        Ok(SegmentNode {
+            column,
+            plan: plan.clone(),
+            facet_children,
+            children: plan
+                .children
+                .iter()
+                .map(|child| SegmentNode::open(child, segment_reader))
+                .collect::<tantivy::Result<_>>()?,
+        })
+    }
+
+    fn bucket_key(&self, key: &BucketKey) -> Option<BucketKey> {
+        match &self.plan.kind {
+            PlanKind::Facet { .. } => match key {
+                BucketKey::U64(ord) => self.facet_children.get(ord).copied().map(BucketKey::U64),
+                _ => None,
+            },
+            PlanKind::Histogram { interval, .. } => key
+                .as_f64()
+                .map(|value| BucketKey::I64((value / interval).floor() as i64)),
+            PlanKind::DateHistogram { interval, .. } => match key {
+                BucketKey::Date(millis) => interval.floor(*millis).map(BucketKey::Date),
+                _ => None,
+            },
+            _ => Some(key.clone()),
+        }
+    }
+
+    fn collect(&self, doc: DocId, partial: &mut Partial) {
+        let values = self.column.read(doc);
+        match partial {
+            Partial::Buckets(buckets) => {
+                let mut keys: Vec<BucketKey> = values
+                    .iter()
+                    .filter_map(|value| self.bucket_key(value))
+                    .collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys {
+                    let bucket = buckets
+                        .entry(key)
+                        .or_insert_with(|| self.plan.empty_bucket());
+                    bucket.doc_count += 1;
+                    for (child, partial) in self.children.iter().zip(&mut bucket.children) {
+                        child.collect(doc, partial);
+                    }
+                }
+            }
+            Partial::Stats(stats) => {
+                for value in values.iter().filter_map(BucketKey::as_f64) {
+                    stats.add(value);
+                }
+            }
+            Partial::Digest(digest) => {
+                for value in values.iter().filter_map(BucketKey::as_f64) {
+                    digest.insert(value);
+                }
+            }
+            Partial::Sketch(sketch) => {
+                for key in values {
+                    if let Some(key) = self.column.resolve(key) {
+                        sketch.insert(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    fn harvest(&self, partial: Partial) -> Partial {
+        match partial {
+            Partial::Buckets(buckets) => Partial::Buckets(
+                buckets
+                    .into_iter()
+                    .filter_map(|(key, bucket)| {
+                        let key = self.column.resolve(key)?;
+                        let children = self
+                            .children
+                            .iter()
+                            .zip(bucket.children)
+                            .map(|(child, partial)| child.harvest(partial))
+                            .collect();
+                        Some((
+                            key,
+                            BucketPartial {
+                                doc_count: bucket.doc_count,
+                                children,
+                            },
+                        ))
+                    })
+                    .collect(),
+            ),
+            partial => partial,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Aggregator {
+    min_score: Score,
+    plans: Vec<Plan>,
+}
+
+impl Aggregator {
+    pub fn new(
+        schema: &Schema,
+        aggregations: &Aggregations,
+        min_score: Score,
+    ) -> Result<Self, EngineError> {
+        let plans = aggregations
+            .iter()
+            .map(|(name, aggregation)| Plan::new(schema, name, aggregation))
+            .collect::<Result<_, _>>()?;
+        Ok(Aggregator { min_score, plans })
+    }
+
+    pub fn finish(
+        &self,
+        partials: Vec<Partial>,
+    ) -> Result<BTreeMap<String, AggregationResult>, EngineError> {
+        self.plans
+            .iter()
+            .zip(partials)
+            .map(|(plan, partial)| Ok((plan.name.clone(), plan.finish(partial)?)))
+            .collect()
+    }
+}
+
+pub(crate) struct SegmentAggregator {
+    min_score: Score,
+    nodes: Vec<SegmentNode>,
+    partials: Vec<Partial>,
+}
+
+impl Collector for Aggregator {
+    type Fruit = Vec<Partial>;
+    type Child = SegmentAggregator;
+
+    fn for_segment(
+        &self,
+        _segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<SegmentAggregator> {
+        Ok(SegmentAggregator {
+            min_score: self.min_score,
+            nodes: self
+                .plans
+                .iter()
+                .map(|plan| SegmentNode::open(plan, segment_reader))
+                .collect::<tantivy::Result<_>>()?,
+            partials: self.plans.iter().map(Plan::partial).collect(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        self.min_score > f32::MIN
+    }
+
+    fn merge_fruits(&self, fruits: Vec<Vec<Partial>>) -> tantivy::Result<Self::Fruit> {
+        let mut merged: Option<Vec<Partial>> = None;
+        for fruit in fruits {
+            match &mut merged {
+                Some(merged) => {
+                    for (partial, other) in merged.iter_mut().zip(fruit) {
+                        partial.merge(other);
+                    }
+                }
+                None => merged = Some(fruit),
+            }
+        }
+        Ok(merged.unwrap_or_else(|| self.plans.iter().map(Plan::partial).collect()))
+    }
+}
+
+impl SegmentCollector for SegmentAggregator {
+    type Fruit = Vec<Partial>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        if score < self.min_score {
+            return;
+        }
+        for (node, partial) in self.nodes.iter().zip(&mut self.partials) {
+            node.collect(doc, partial);
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        self.nodes
+            .iter()
+            .zip(self.partials)
+            .map(|(node, partial)| node.harvest(partial))
+            .collect()
+    }
+}
@@ -0,0 +1,61 @@
+use tantivy::tokenizer::{
+    BoxTokenStream, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer,
+    StopWordFilter, TextAnalyzer, Token, TokenFilter, TokenStream,
+};
+use tantivy::Index;
+
+pub const ANALYZERS: &[&str] = &["default", "raw", "whitespace", "en_stem", "russian"];
+
+pub fn is_known(name: &str) -> bool {
+    ANALYZERS.contains(&name)
+}
+
+pub fn is_stemming(name: &str) -> bool {
+    matches!(name, "en_stem" | "russian")
+}
+
+pub fn register(index: &Index) {
+    index.tokenizers().register(
+        "russian",
+        TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .filter(YoFolding)
+            .filter(StopWordFilter::new(Language::Russian).expect("russian stop words"))
+            .filter(Stemmer::new(Language::Russian)),
+    );
+}
+
+#[derive(Clone)]
+struct YoFolding;
+
+impl TokenFilter for YoFolding {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(YoFoldingStream { tail: token_stream })
+    }
+}
+
+struct YoFoldingStream<'a> {
+    tail: BoxTokenStream<'a>,
+}
+
+impl TokenStream for YoFoldingStream<'_> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        let text = &mut self.tail.token_mut().text;
+        if text.contains(['ё', 'Ё']) {
+            *text = text.replace('ё', "е").replace('Ё', "Е");
+        }
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
@@ -0,0 +1,194 @@
+use super::document::{Document, WriteResponse, WriteResult};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkAction {
+    Index,
+    Delete,
+}
+
+#[derive(Clone, Debug)]
+pub enum BulkOperation {
+    Index {
+        doc: Document,
+        if_version: Option<u64>,
+    },
+    Delete {
+        id: String,
+        if_version: Option<u64>,
+    },
+    Invalid {
+        action: BulkAction,
+        id: Option<String>,
+        error: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BulkItemStatus {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<WriteResult>,
+    #[serde(rename = "_version", skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(rename = "_seq_no", skip_serializing_if = "Option::is_none")]
+    pub seq_no: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkItemResponse {
+    Index(BulkItemStatus),
+    Delete(BulkItemStatus),
+}
+
+impl BulkItemStatus {
+    pub fn success(status: u16, write: WriteResponse) -> Self {
+        BulkItemStatus {
+            id: Some(write.id),
+            status,
+            result: Some(write.result),
+            version: Some(write.version),
+            seq_no: Some(write.seq_no),
+            error: None,
+        }
+    }
+
+    pub fn failure(id: Option<String>, status: u16, error: String) -> Self {
+        BulkItemStatus {
+            id,
+            status,
+            result: None,
+            version: None,
+            seq_no: None,
+            error: Some(error),
+        }
+    }
+
+    pub fn with_action(self, action: BulkAction) -> BulkItemResponse {
+        BulkItemResponse::new(action, self)
+    }
+}
+
+impl BulkItemResponse {
+    pub fn new(action: BulkAction, status: BulkItemStatus) -> Self {
+        match action {
+            BulkAction::Index => BulkItemResponse::Index(status),
+            BulkAction::Delete => BulkItemResponse::Delete(status),
+        }
+    }
+
+    pub fn status(&self) -> &BulkItemStatus {
+        match self {
+            BulkItemResponse::Index(status) | BulkItemResponse::Delete(status) => status,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.status().error.is_some()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ActionMeta {
+    #[serde(rename = "_id")]
+    id: Option<String>,
+    if_version: Option<u64>,
+}
+
+pub fn parse_ndjson(body: &str) -> Result<Vec<BulkOperation>> {
+    let mut operations = Vec::new();
+    let mut lines = body
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    while let Some((line_no, line)) = lines.next() {
+        let (action, meta) =
+            parse_action(line).map_err(|e| anyhow!("line {}: {}", line_no + 1, e))?;
+
+        let operation = match action {
+            BulkAction::Delete => match meta.id {
+                Some(id) => BulkOperation::Delete {
+                    id,
+                    if_version: meta.if_version,
+                },
+                None => BulkOperation::Invalid {
+                    action,
+                    id: None,
+                    error: "delete action requires an _id".to_string(),
+                },
+            },
+            BulkAction::Index => {
+                let (_, source) = lines.next().ok_or_else(|| {
+                    anyhow!(
+                        "line {}: index action is missing a source line",
+                        line_no + 1
+                    )
+                })?;
+                match parse_source(source, meta.id.clone()) {
+                    Ok(doc) => BulkOperation::Index {
+                        doc,
+                        if_version: meta.if_version,
+                    },
+                    Err(e) => BulkOperation::Invalid {
+                        action,
+                        id: meta.id,
+                        error: e.to_string(),
+                    },
+                }
+            }
+        };
+
+        operations.push(operation);
+    }
+
+    Ok(operations)
+}
+
+fn parse_action(line: &str) -> Result<(BulkAction, ActionMeta)> {
+    let value: Value = serde_json::from_str(line)?;
+    let object = match value {
+        Value::Object(object) if object.len() == 1 => object,
+        _ => bail!("action line must be an object with a single action"),
+    };
+
+    let (name, meta) = object.into_iter().next().unwrap();
+    let action: BulkAction = serde_json::from_value(Value::String(name.clone()))
+        .map_err(|_| anyhow!("unknown bulk action '{}'", name))?;
+    let meta: ActionMeta = serde_json::from_value(meta)?;
+
+    Ok((action, meta))
+}
+
+fn parse_source(line: &str, id: Option<String>) -> Result<Document> {
+    let mut source: Value = serde_json::from_str(line)?;
+    let object = source
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("document source must be an object"))?;
+
+    if let Some(id) = id {
+        match object.get("id") {
+            Some(Value::String(existing)) if *existing != id => {
+                bail!("_id '{}' does not match document id '{}'", id, existing)
+            }
+            _ => {
+                object.insert("id".to_string(), Value::String(id));
+            }
+        }
+    }
+
+    let doc: Document = serde_json::from_value(source)?;
+    if doc.id.is_empty() {
+        bail!("document id must not be empty");
+    }
+
+    Ok(doc)
+}
@@ -0,0 +1,126 @@
+use super::value::parse_date;
+use crate::common::error::EngineError;
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::time::{Date, Duration, Month, OffsetDateTime, Time};
+use tantivy::DateTime;
+
+pub fn is_date_math(expr: &str) -> bool {
+    expr.starts_with("now") || expr.contains("||")
+}
+
+pub fn resolve(expr: &str) -> Result<DateTime, EngineError> {
+    resolve_at(expr, OffsetDateTime::now_utc())
+}
+
+pub fn resolve_at(expr: &str, now: OffsetDateTime) -> Result<DateTime, EngineError> {
+    let invalid = || EngineError::InvalidQuery(format!("invalid date math expression '{}'", expr));
+
+    let (mut date, mut rest) = match expr.strip_prefix("now") {
+        Some(rest) => (now, rest),
+        None => {
+            let (anchor, rest) = expr.split_once("||").ok_or_else(invalid)?;
+            let anchor = parse_date(anchor).ok_or_else(invalid)?.into_utc();
+            (anchor, rest)
+        }
+    };
+
+    while let Some(op) = rest.chars().next() {
+        rest = &rest[op.len_utf8()..];
+        match op {
+            '+' | '-' => {
+                let digits = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .ok_or_else(invalid)?;
+                let amount: i64 = rest[..digits].parse().map_err(|_| invalid())?;
+                let amount = if op == '-' { -amount } else { amount };
+                let unit = rest[digits..].chars().next().ok_or_else(invalid)?;
+                date = shift(date, amount, unit).ok_or_else(invalid)?;
+                rest = &rest[digits + unit.len_utf8()..];
+            }
+            '/' => {
+                let unit = rest.chars().next().ok_or_else(invalid)?;
+                date = round_down(date, unit).ok_or_else(invalid)?;
+                rest = &rest[unit.len_utf8()..];
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(DateTime::from_utc(date))
+}
+
+pub fn rewrite_bound(token: &str) -> Result<String, EngineError> {
+    let start = token
+        .find(|c: char| !matches!(c, '>' | '<' | '=' | '[' | '{'))
+        .unwrap_or(token.len());
+    let end = token
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !matches!(c, ']' | '}'))
+        .map_or(start, |(end, c)| end + c.len_utf8())
+        .max(start);
+    let (prefix, expr, suffix) = (&token[..start], &token[start..end], &token[end..]);
+
+    if !is_date_math(expr) {
+        return Ok(token.to_string());
+    }
+
+    let date = resolve(expr)?
+        .into_utc()
+        .replace_nanosecond(0)
+        .ok()
+        .and_then(|date| date.format(&Rfc3339).ok())
+        .ok_or_else(|| EngineError::InvalidQuery(format!("invalid date '{}'", expr)))?;
+    Ok(format!("{}{}{}", prefix, date, suffix))
+}
+
+pub(crate) fn shift(date: OffsetDateTime, amount: i64, unit: char) -> Option<OffsetDateTime> {
+    match unit {
+        'y' => shift_months(date, amount.checked_mul(12)?),
+        'M' => shift_months(date, amount),
+        'w' => shift_seconds(date, amount, 7 * 86_400),
+        'd' => shift_seconds(date, amount, 86_400),
+        'h' | 'H' => shift_seconds(date, amount, 3_600),
+        'm' => shift_seconds(date, amount, 60),
+        's' => shift_seconds(date, amount, 1),
+        _ => None,
+    }
+}
+
+fn shift_seconds(date: OffsetDateTime, amount: i64, unit_seconds: i64) -> Option<OffsetDateTime> {
+    let seconds = amount.checked_mul(unit_seconds)?;
+    date.checked_add(Duration::seconds(seconds))
+}
+
+fn shift_months(date: OffsetDateTime, months: i64) -> Option<OffsetDateTime> {
+    let total = date.year() as i64 * 12 + (date.month() as i64 - 1) + months;
+    let year = i32::try_from(total.div_euclid(12)).ok()?;
+    let month = Month::try_from(total.rem_euclid(12) as u8 + 1).ok()?;
+    let day = date.day().min(month.length(year));
+    Some(date.replace_date(Date::from_calendar_date(year, month, day).ok()?))
+}
+
+pub(crate) fn round_down(date: OffsetDateTime, unit: char) -> Option<OffsetDateTime> {
+    let midnight = date.replace_time(Time::MIDNIGHT);
+    match unit {
+        'y' => date
+            .replace_date(Date::from_calendar_date(date.year(), Month::January, 1).ok()?)
+            .replace_time(Time::MIDNIGHT)
+            .into(),
+        'M' => midnight.replace_day(1).ok(),
+        'w' => shift_seconds(
+            midnight,
+            -(date.weekday().number_days_from_monday() as i64),
+            86_400,
+        ),
+        'd' => Some(midnight),
+        'h' | 'H' => date
+            .replace_time(Time::from_hms(date.hour(), 0, 0).ok()?)
+            .into(),
+        'm' => date
+            .replace_time(Time::from_hms(date.hour(), date.minute(), 0).ok()?)
+            .into(),
+        's' => date.replace_nanosecond(0).ok(),
+        _ => None,
+    }
+}
@@ -1,9 +1,120 @@
+use crate::common::error::EngineError;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Document {
     pub id: String,
     pub content: String,
-    pub metadata: HashMap<String, String>,
+    pub metadata: HashMap<String, Value>,
+    #[serde(rename = "_version", default)]
+    pub version: u64,
+    #[serde(rename = "_seq_no", default)]
+    pub seq_no: u64,
+}
+
+impl Document {
+    pub fn project(&self, fields: &[&str]) -> Value {
+        let mut projected = json!({
+            "id": self.id,
+            "_version": self.version,
+            "_seq_no": self.seq_no
+        });
+        let mut metadata = Map::new();
+
+        for field in fields {
+            if *field == "content" {
+                projected["content"] = json!(self.content);
+                continue;
+            }
+
+            let key = field.strip_prefix("metadata.").unwrap_or(field);
+            if let Some(value) = self.metadata.get(key) {
+                metadata.insert(key.to_string(), value.clone());
+            }
+        }
+
+        projected["metadata"] = Value::Object(metadata);
+        projected
+    }
+
+    pub fn merge_patch(&self, patch: &Value) -> Result<Document, EngineError> {
+        if !patch.is_object() {
+            return Err(EngineError::InvalidDocument(
+                "patch must be a JSON object".to_string(),
+            ));
+        }
+        if let Some(id) = patch.get("id") {
+            if *id != json!(self.id) {
+                return Err(EngineError::InvalidDocument(
+                    "document id cannot be changed".to_string(),
+                ));
+            }
+        }
+
+        let mut merged = json!(self);
+        merge_value(&mut merged, patch);
+
+        serde_json::from_value(merged).map_err(|e| EngineError::InvalidDocument(e.to_string()))
+    }
+}
+
+fn merge_value(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(key);
+                } else {
+                    merge_value(target.entry(key.clone()).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteResult {
+    Created,
+    Updated,
+    Deleted,
+    NotFound,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteResponse {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub result: WriteResult,
+    #[serde(rename = "_version")]
+    pub version: u64,
+    #[serde(rename = "_seq_no")]
+    pub seq_no: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Refresh {
+    True,
+    WaitFor,
+    #[default]
+    False,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    pub if_version: Option<u64>,
+    pub refresh: Refresh,
+}
+
+impl WriteOptions {
+    pub fn immediate() -> Self {
+        WriteOptions {
+            refresh: Refresh::True,
+            ..WriteOptions::default()
+        }
+    }
 }
@@ -0,0 +1,493 @@
+use super::date_math;
+use super::fuzzy::{fuzzy_query, Fuzziness};
+use super::language::{Language, LANGUAGE_BOOST};
+use super::mapping::keyword_field;
+use super::value::to_term;
+use crate::common::error::EngineError;
+use serde::de::{DeserializeOwned, Deserializer};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, ConstScoreQuery, EmptyQuery, Occur, PhraseQuery, Query,
+    RangeQuery, RegexQuery, TermQuery, TermSetQuery,
+};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema};
+use tantivy::{Index, Term};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryDsl {
+    MatchAll(MatchAllQuery),
+    Match(FieldQuery<MatchQuery>),
+    MatchPhrase(FieldQuery<MatchQuery>),
+    Term(FieldQuery<TermValue>),
+    Terms(FieldQuery<Vec<Value>>),
+    Range(FieldQuery<RangeBounds>),
+    Bool(BoolQuery),
+    Exists(ExistsQuery),
+    Prefix(FieldQuery<TermValue>),
+    Wildcard(FieldQuery<TermValue>),
+}
+
+impl Default for QueryDsl {
+    fn default() -> Self {
+        QueryDsl::MatchAll(MatchAllQuery {})
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatchAllQuery {}
+
+#[derive(Clone, Debug)]
+pub struct FieldQuery<T> {
+    pub field: String,
+    pub value: T,
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for FieldQuery<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = BTreeMap::<String, T>::deserialize(deserializer)?;
+        if map.len() != 1 {
+            return Err(serde::de::Error::custom(format!(
+                "expected a single field, got {}",
+                map.len()
+            )));
+        }
+        let (field, value) = map.into_iter().next().unwrap();
+        Ok(FieldQuery { field, value })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    #[default]
+    Or,
+    And,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "MatchSpec")]
+pub struct MatchQuery {
+    pub query: Value,
+    pub operator: Operator,
+    pub fuzziness: Option<Fuzziness>,
+    pub prefix_length: usize,
+    pub slop: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MatchSpec {
+    Full {
+        query: Value,
+        #[serde(default)]
+        operator: Operator,
+        #[serde(default)]
+        fuzziness: Option<Fuzziness>,
+        #[serde(default)]
+        prefix_length: usize,
+        #[serde(default)]
+        slop: u32,
+    },
+    Short(Value),
+}
+
+impl From<MatchSpec> for MatchQuery {
+    fn from(spec: MatchSpec) -> Self {
+        match spec {
+            MatchSpec::Full {
+                query,
+                operator,
+                fuzziness,
+                prefix_length,
+                slop,
+            } => MatchQuery {
+                query,
+                operator,
+                fuzziness,
+                prefix_length,
+                slop,
+            },
+            MatchSpec::Short(query) => MatchQuery {
+                query,
+                operator: Operator::Or,
+                fuzziness: None,
+                prefix_length: 0,
+                slop: 0,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "TermSpec")]
+pub struct TermValue {
+    pub value: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TermSpec {
+    Full { value: Value },
+    Short(Value),
+}
+
+impl From<TermSpec> for TermValue {
+    fn from(spec: TermSpec) -> Self {
+        match spec {
+            TermSpec::Full { value } | TermSpec::Short(value) => TermValue { value },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RangeBounds {
+    pub gt: Option<Value>,
+    pub gte: Option<Value>,
+    pub lt: Option<Value>,
+    pub lte: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoolQuery {
+    #[serde(deserialize_with = "one_or_many")]
+    pub must: Vec<QueryDsl>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub should: Vec<QueryDsl>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub must_not: Vec<QueryDsl>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub filter: Vec<QueryDsl>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExistsQuery {
+    pub field: String,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<QueryDsl>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        Many(Vec<QueryDsl>),
+        One(Box<QueryDsl>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::Many(queries) => queries,
+        OneOrMany::One(query) => vec![*query],
+    })
+}
+
+impl QueryDsl {
+    pub fn compile(&self, index: &Index) -> Result<Box<dyn Query>, EngineError> {
+        let compiler = Compiler {
+            index,
+            schema: index.schema(),
+        };
+        compiler.compile(self)
+    }
+}
+
+struct Compiler<'a> {
+    index: &'a Index,
+    schema: Schema,
+}
+
+impl Compiler<'_> {
+    fn compile(&self, query: &QueryDsl) -> Result<Box<dyn Query>, EngineError> {
+        match query {
+            QueryDsl::MatchAll(_) => Ok(Box::new(AllQuery)),
+            QueryDsl::Match(query) if query.field == "content" => self.content_query(&query.value),
+            QueryDsl::Match(query) => self.field(&query.field, |field, field_type| {
+                self.match_query(field, field_type, &query.value)
+            }),
+            QueryDsl::MatchPhrase(query) => self.field(&query.field, |field, field_type| {
+                self.phrase_query(field, field_type, &query.value)
+            }),
+            QueryDsl::Term(query) => self.exact_field(&query.field, |field, field_type| {
+                Ok(term_query(self.term(
+                    field,
+                    field_type,
+                    &query.field,
+                    &query.value.value,
+                )?))
+            }),
+            QueryDsl::Terms(query) => self.exact_field(&query.field, |field, field_type| {
+                let terms = query
+                    .value
+                    .iter()
+                    .map(|value| self.term(field, field_type, &query.field, value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Box::new(TermSetQuery::new(terms)))
+            }),
+            QueryDsl::Range(query) => self.field(&query.field, |field, field_type| {
+                self.range_query(field, field_type, &query.field, &query.value)
+            }),
+            QueryDsl::Bool(query) => self.bool_query(query),
+            QueryDsl::Exists(query) => {
+                let field_names = self.schema.get_field("_field_names").unwrap();
+                Ok(term_query(Term::from_field_text(field_names, &query.field)))
+            }
+            QueryDsl::Prefix(query) => self.field(&query.field, |field, field_type| {
+                let prefix = self.text(field_type, &query.field, &query.value.value)?;
+                self.regex_query(field, &format!("{}.*", escape_regex(&prefix)))
+            }),
+            QueryDsl::Wildcard(query) => self.field(&query.field, |field, field_type| {
+                let pattern = self.text(field_type, &query.field, &query.value.value)?;
+                self.regex_query(field, &wildcard_to_regex(&pattern))
+            }),
+        }
+    }
+
+    fn field(
+        &self,
+        name: &str,
+        build: impl FnOnce(Field, &FieldType) -> Result<Box<dyn Query>, EngineError>,
+    ) -> Result<Box<dyn Query>, EngineError> {
+        match self.schema.get_field(name) {
+            Some(field) if !name.starts_with('_') => {
+                build(field, self.schema.get_field_entry(field).field_type())
+            }
+            _ => Ok(Box::new(EmptyQuery)),
+        }
+    }
+
+    fn exact_field(
+        &self,
+        name: &str,
+        build: impl FnOnce(Field, &FieldType) -> Result<Box<dyn Query>, EngineError>,
+    ) -> Result<Box<dyn Query>, EngineError> {
+        match self.schema.get_field(&keyword_field(name)) {
+            Some(field) if !name.starts_with('_') => {
+                build(field, self.schema.get_field_entry(field).field_type())
+            }
+            _ => self.field(name, build),
+        }
+    }
+
+    fn term(
+        &self,
+        field: Field,
+        field_type: &FieldType,
+        name: &str,
+        value: &Value,
+    ) -> Result<Term, EngineError> {
+        let resolved;
+        let value = match (field_type, value) {
+            (FieldType::Date(_), Value::String(text)) if date_math::is_date_math(text) => {
+                resolved = Value::from(date_math::resolve(text)?.into_timestamp_millis());
+                &resolved
+            }
+            _ => value,
+        };
+
+        to_term(field, field_type, value).ok_or_else(|| {
+            EngineError::InvalidQuery(format!(
+                "value {} is not valid for field '{}' of type {:?}",
+                value,
+                name,
+                field_type.value_type()
+            ))
+        })
+    }
+
+    fn text(
+        &self,
+        field_type: &FieldType,
+        name: &str,
+        value: &Value,
+    ) -> Result<String, EngineError> {
+        match (field_type, value) {
+            (FieldType::Str(_), Value::String(text)) => Ok(text.clone()),
+            _ => Err(EngineError::InvalidQuery(format!(
+                "field '{}' requires a string pattern on a text or keyword field",
+                name
+            ))),
+        }
+    }
+
+    fn tokens(&self, field: Field, text: &str) -> Result<Vec<(usize, Term)>, EngineError> {
+        let analyzer = self
+            .index
+            .tokenizer_for_field(field)
+            .map_err(|e| EngineError::InvalidQuery(e.to_string()))?;
+        let mut stream = analyzer.token_stream(text);
+        let mut tokens = Vec::new();
+        while stream.advance() {
+            let token = stream.token();
+            tokens.push((token.position, Term::from_field_text(field, &token.text)));
+        }
+        Ok(tokens)
+    }
+
+    fn match_query(
+        &self,
+        field: Field,
+        field_type: &FieldType,
+        query: &MatchQuery,
+    ) -> Result<Box<dyn Query>, EngineError> {
+        let text = match (field_type, &query.query) {
+            (FieldType::Str(_), Value::String(text)) => text,
+            (_, value) => {
+                let name = self.schema.get_field_name(field);
+                return Ok(term_query(self.term(field, field_type, name, value)?));
+            }
+        };
+
+        let occur = match query.operator {
+            Operator::Or => Occur::Should,
+            Operator::And => Occur::Must,
+        };
+        let clauses: Vec<(Occur, Box<dyn Query>)> = self
+            .tokens(field, text)?
+            .into_iter()
+            .map(|(_, term)| match query.fuzziness {
+                Some(fuzziness) => (occur, fuzzy_query(term, fuzziness, query.prefix_length)),
+                None => (occur, term_query(term)),
+            })
+            .collect();
+
+        Ok(match clauses.len() {
+            0 => Box::new(EmptyQuery),
+            _ => Box::new(BooleanQuery::new(clauses)),
+        })
+    }
+
+    fn content_query(&self, query: &MatchQuery) -> Result<Box<dyn Query>, EngineError> {
+        let detected = query.query.as_str().and_then(Language::detect);
+        let content = self.field("content", |field, field_type| {
+            self.match_query(field, field_type, query)
+        })?;
+
+        let mut clauses = vec![(Occur::Should, content)];
+        for language in Language::ALL {
+            let field = self.schema.get_field(&language.field()).unwrap();
+            let field_type = self.schema.get_field_entry(field).field_type();
+            let clause = self.match_query(field, field_type, query)?;
+            let clause: Box<dyn Query> = match Some(language) == detected {
+                true => Box::new(BoostQuery::new(clause, LANGUAGE_BOOST)),
+                false => clause,
+            };
+            clauses.push((Occur::Should, clause));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    fn phrase_query(
+        &self,
+        field: Field,
+        field_type: &FieldType,
+        query: &MatchQuery,
+    ) -> Result<Box<dyn Query>, EngineError> {
+        let text = match (field_type, &query.query) {
+            (FieldType::Str(_), Value::String(text)) => text,
+            (_, value) => {
+                let name = self.schema.get_field_name(field);
+                return Ok(term_query(self.term(field, field_type, name, value)?));
+            }
+        };
+
+        let mut tokens = self.tokens(field, text)?;
+        Ok(match tokens.len() {
+            0 => Box::new(EmptyQuery),
+            1 => term_query(tokens.remove(0).1),
+            _ => Box::new(PhraseQuery::new_with_offset_and_slop(tokens, query.slop)),
+        })
+    }
+
+    fn range_query(
+        &self,
+        field: Field,
+        field_type: &FieldType,
+        name: &str,
+        bounds: &RangeBounds,
+    ) -> Result<Box<dyn Query>, EngineError> {
+        let bound =
+            |inclusive: &Option<Value>, exclusive: &Option<Value>| match (inclusive, exclusive) {
+                (Some(_), Some(_)) => Err(EngineError::InvalidQuery(format!(
+                    "range on '{}' cannot combine inclusive and exclusive bounds on the same side",
+                    name
+                ))),
+                (Some(value), None) => {
+                    Ok(Bound::Included(self.term(field, field_type, name, value)?))
+                }
+                (None, Some(value)) => {
+                    Ok(Bound::Excluded(self.term(field, field_type, name, value)?))
+                }
+                (None, None) => Ok(Bound::Unbounded),
+            };
+
+        let lower = bound(&bounds.gte, &bounds.gt)?;
+        let upper = bound(&bounds.lte, &bounds.lt)?;
+        Ok(Box::new(RangeQuery::new_term_bounds(
+            field,
+            field_type.value_type(),
+            &lower,
+            &upper,
+        )))
+    }
+
+    fn bool_query(&self, query: &BoolQuery) -> Result<Box<dyn Query>, EngineError> {
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for must in &query.must {
+            clauses.push((Occur::Must, self.compile(must)?));
+        }
+        for filter in &query.filter {
+            clauses.push((
+                Occur::Must,
+                Box::new(ConstScoreQuery::new(self.compile(filter)?, 0.0)),
+            ));
+        }
+        for should in &query.should {
+            clauses.push((Occur::Should, self.compile(should)?));
+        }
+        for must_not in &query.must_not {
+            clauses.push((Occur::MustNot, self.compile(must_not)?));
+        }
+
+        if clauses.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+            clauses.push((Occur::Must, Box::new(AllQuery)));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    fn regex_query(&self, field: Field, pattern: &str) -> Result<Box<dyn Query>, EngineError> {
+        RegexQuery::from_pattern(pattern, field)
+            .map(|query| Box::new(query) as Box<dyn Query>)
+            .map_err(|e| EngineError::InvalidQuery(e.to_string()))
+    }
+}
+
+fn term_query(term: Term) -> Box<dyn Query> {
+    Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs))
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~\"".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn wildcard_to_regex(pattern: &str) -> String {
+    pattern
+        .chars()
+        .map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => escape_regex(&c.to_string()),
+        })
+        .collect()
+}
@@ -0,0 +1,434 @@
+use crate::common::error::EngineError;
+use serde::Deserialize;
+use serde_json::Value;
+use std::str::FromStr;
+use tantivy::query::{
+    BooleanQuery, BoostQuery, ConstScorer, EmptyQuery, EnableScoring, Explanation, FuzzyTermQuery,
+    Occur, PhraseQuery, Query, QueryParser, Scorer, TermQuery, Weight,
+};
+use tantivy::schema::{Field, IndexRecordOption};
+use tantivy::{DocId, DocSet, Index, Score, SegmentReader, TantivyError, Term, TERMINATED};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Value")]
+pub enum Fuzziness {
+    Auto,
+    Edits(u8),
+}
+
+impl Fuzziness {
+    pub fn distance(self, term: &str) -> u8 {
+        match self {
+            Fuzziness::Edits(edits) => edits,
+            Fuzziness::Auto => match term.chars().count() {
+                0..=2 => 0,
+                3..=5 => 1,
+                _ => 2,
+            },
+        }
+    }
+}
+
+impl FromStr for Fuzziness {
+    type Err = EngineError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" | "AUTO" => Ok(Fuzziness::Auto),
+            "0" => Ok(Fuzziness::Edits(0)),
+            "1" => Ok(Fuzziness::Edits(1)),
+            "2" => Ok(Fuzziness::Edits(2)),
+            _ => Err(EngineError::InvalidQuery(format!(
+                "fuzziness must be 'auto', 0, 1 or 2, got '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+impl TryFrom<Value> for Fuzziness {
+    type Error = EngineError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(text) => text.parse(),
+            Value::Number(number) => number.to_string().parse(),
+            value => Err(EngineError::InvalidQuery(format!(
+                "fuzziness must be 'auto', 0, 1 or 2, got {}",
+                value
+            ))),
+        }
+    }
+}
+
+pub fn fuzzy_query(term: Term, fuzziness: Fuzziness, prefix_length: usize) -> Box<dyn Query> {
+    let text = term.as_str().unwrap_or_default().to_string();
+    match fuzziness.distance(&text) {
+        0 => Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs)),
+        distance if prefix_length == 0 => Box::new(FuzzyTermQuery::new(term, distance, true)),
+        distance => Box::new(FuzzyPrefixQuery {
+            field: term.field(),
+            text,
+            distance,
+            prefix_length,
+        }),
+    }
+}
+
+#[derive(Clone, Debug)]
+struct FuzzyPrefixQuery {
+    field: Field,
+    text: String,
+    distance: u8,
+    prefix_length: usize,
+}
+
+impl Query for FuzzyPrefixQuery {
+    fn weight(&self, _enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+impl Weight for FuzzyPrefixQuery {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let prefix: String = self.text.chars().take(self.prefix_length).collect();
+        let target: Vec<char> = self.text.chars().collect();
+        let inverted_index = reader.inverted_index(self.field)?;
+        let mut stream = inverted_index
+            .terms()
+            .range()
+            .ge(prefix.as_bytes())
+            .into_stream()?;
+
+        let mut docs = Vec::new();
+        while stream.advance() {
+            if !stream.key().starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let Ok(candidate) = std::str::from_utf8(stream.key()) else {
+                continue;
+            };
+            if edit_distance(&target, candidate, self.distance as usize).is_none() {
+                continue;
+            }
+            let mut postings = inverted_index
+                .read_block_postings_from_terminfo(stream.value(), IndexRecordOption::Basic)?;
+            while !postings.docs().is_empty() {
+                docs.extend_from_slice(postings.docs());
+                postings.advance();
+            }
+        }
+        docs.sort_unstable();
+        docs.dedup();
+        Ok(Box::new(ConstScorer::new(
+            MatchedDocs { docs, cursor: 0 },
+            boost,
+        )))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) == doc {
+            Ok(Explanation::new("FuzzyPrefixQuery", 1.0))
+        } else {
+            Err(TantivyError::InvalidArgument(format!(
+                "document #({}) does not match",
+                doc
+            )))
+        }
+    }
+}
+
+struct MatchedDocs {
+    docs: Vec<DocId>,
+    cursor: usize,
+}
+
+impl DocSet for MatchedDocs {
+    fn advance(&mut self) -> DocId {
+        self.cursor += 1;
+        self.doc()
+    }
+
+    fn doc(&self) -> DocId {
+        self.docs.get(self.cursor).copied().unwrap_or(TERMINATED)
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.docs.len() as u32
+    }
+}
+
+pub(crate) fn edit_distance(target: &[char], candidate: &str, max: usize) -> Option<usize> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    if candidate.len().abs_diff(target.len()) > max {
+        return None;
+    }
+
+    let mut previous: Vec<usize> = Vec::new();
+    let mut current: Vec<usize> = (0..=candidate.len()).collect();
+    for i in 1..=target.len() {
+        let before = std::mem::replace(&mut previous, current.clone());
+        current[0] = i;
+        for j in 1..=candidate.len() {
+            let cost = usize::from(target[i - 1] != candidate[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1
+                && j > 1
+                && target[i - 1] == candidate[j - 2]
+                && target[i - 2] == candidate[j - 1]
+            {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        if current.iter().all(|distance| *distance > max) {
+            return None;
+        }
+    }
+    Some(current[candidate.len()]).filter(|distance| *distance <= max)
+}
+
+#[derive(Clone, Debug)]
+struct FuzzyTerm {
+    field: Option<Field>,
+    word: String,
+    fuzziness: Fuzziness,
+}
+
+#[derive(Clone, Debug)]
+pub struct QueryPart {
+    text: String,
+    fuzzy: Option<FuzzyTerm>,
+}
+
+impl QueryPart {
+    pub fn new(text: String) -> Self {
+        QueryPart { text, fuzzy: None }
+    }
+
+    pub fn term(text: String, field: Option<Field>, value: &str) -> Self {
+        let body = value
+            .trim_start_matches(['+', '-', '('])
+            .trim_end_matches(')');
+        let fuzzy = body.split_once('~').and_then(|(word, edits)| {
+            if word.is_empty() || !word.chars().all(char::is_alphanumeric) {
+                return None;
+            }
+            let fuzziness = match edits {
+                "" => Fuzziness::Auto,
+                edits => edits.parse().ok()?,
+            };
+            Some(FuzzyTerm {
+                field,
+                word: word.to_string(),
+                fuzziness,
+            })
+        });
+        QueryPart { text, fuzzy }
+    }
+
+    fn occur(&self) -> Option<Occur> {
+        match self.text.chars().next()? {
+            '+' => Some(Occur::Must),
+            '-' => Some(Occur::MustNot),
+            _ => None,
+        }
+    }
+
+    fn is_operator(&self) -> bool {
+        self.fuzzy.is_none() && matches!(self.text.as_str(), "AND" | "OR")
+    }
+}
+
+pub struct FuzzyQueryBuilder<'a> {
+    pub index: &'a Index,
+    pub parser: &'a QueryParser,
+    pub fields: &'a [Field],
+    pub prefix_length: usize,
+    pub boost: Option<(Field, Score)>,
+}
+
+impl FuzzyQueryBuilder<'_> {
+    pub fn build(&self, parts: &[QueryPart]) -> anyhow::Result<Box<dyn Query>> {
+        if parts.iter().all(|part| part.fuzzy.is_none()) {
+            return self.parse(parts);
+        }
+
+        let leaves = split_leaves(parts);
+        let is_expression = leaves.len() % 2 == 1
+            && leaves.iter().enumerate().all(|(position, leaf)| {
+                let is_operator = leaf.len() == 1 && leaf[0].is_operator();
+                is_operator == (position % 2 == 1) && (is_operator || leaf[0].occur().is_none())
+            });
+
+        if is_expression && leaves.len() > 1 {
+            let mut disjuncts = vec![vec![self.clause(&leaves[0], Occur::Must)?]];
+            for pair in leaves[1..].chunks(2) {
+                let clause = self.clause(&pair[1], Occur::Must)?;
+                match pair[0][0].text.as_str() {
+                    "OR" => disjuncts.push(vec![clause]),
+                    _ => disjuncts.last_mut().unwrap().push(clause),
+                }
+            }
+            let mut disjuncts: Vec<Box<dyn Query>> = disjuncts
+                .into_iter()
+                .map(|mut clauses| match clauses.len() {
+                    1 if clauses[0].0 == Occur::Must => clauses.remove(0).1,
+                    _ => Box::new(BooleanQuery::new(clauses)) as Box<dyn Query>,
+                })
+                .collect();
+            return Ok(match disjuncts.len() {
+                1 => disjuncts.remove(0),
+                _ => combine(disjuncts, Occur::Should),
+            });
+        }
+
+        let mut clauses = Vec::with_capacity(leaves.len());
+        for mut leaf in leaves {
+            match leaf[0].occur() {
+                Some(occur) => {
+                    leaf[0].text.remove(0);
+                    clauses.push((occur, self.leaf(&leaf)?));
+                }
+                None => clauses.push(self.clause(&leaf, Occur::Should)?),
+            }
+        }
+        Ok(match clauses.len() {
+            1 if clauses[0].0 != Occur::MustNot => clauses.remove(0).1,
+            _ => Box::new(BooleanQuery::new(clauses)),
+        })
+    }
+
+    fn clause(&self, parts: &[QueryPart], occur: Occur) -> anyhow::Result<(Occur, Box<dyn Query>)> {
+        match parts {
+            [not, negated @ ..] if not.text == "NOT" && !negated.is_empty() => {
+                Ok((Occur::MustNot, self.leaf(negated)?))
+            }
+            _ => Ok((occur, self.leaf(parts)?)),
+        }
+    }
+
+    fn leaf(&self, parts: &[QueryPart]) -> anyhow::Result<Box<dyn Query>> {
+        match parts {
+            [part] if !part.text.starts_with('(') => match &part.fuzzy {
+                Some(term) => self.fuzzy(term),
+                None => self.parse(parts),
+            },
+            [first, .., last] | [first @ last]
+                if first.text.starts_with('(')
+                    && last.text.ends_with(')')
+                    && parts.iter().any(|part| part.fuzzy.is_some()) =>
+            {
+                let mut inner = parts.to_vec();
+                inner[0].text.remove(0);
+                let last = inner.len() - 1;
+                inner[last].text.pop();
+                inner.retain(|part| !part.text.is_empty());
+                self.build(&inner)
+            }
+            _ => self.parse(parts),
+        }
+    }
+
+    fn parse(&self, parts: &[QueryPart]) -> anyhow::Result<Box<dyn Query>> {
+        let text: Vec<&str> = parts.iter().map(|part| part.text.as_str()).collect();
+        let query = self.parser.parse_query(&text.join(" "))?;
+        Ok(self.boost_terms(query))
+    }
+
+    fn fuzzy(&self, term: &FuzzyTerm) -> anyhow::Result<Box<dyn Query>> {
+        let fields = match term.field {
+            Some(field) => vec![field],
+            None => self.fields.to_vec(),
+        };
+        let mut queries = Vec::with_capacity(fields.len());
+        for field in fields {
+            let analyzer = self
+                .index
+                .tokenizer_for_field(field)
+                .map_err(|e| EngineError::InvalidQuery(e.to_string()))?;
+            let mut stream = analyzer.token_stream(&term.word);
+            if !stream.advance() {
+                continue;
+            }
+            let query = fuzzy_query(
+                Term::from_field_text(field, &stream.token().text),
+                term.fuzziness,
+                self.prefix_length,
+            );
+            queries.push(match self.boost {
+                Some((boosted, boost)) if boosted == field => {
+                    Box::new(BoostQuery::new(query, boost))
+                }
+                _ => query,
+            });
+        }
+        Ok(match queries.len() {
+            0 => Box::new(EmptyQuery),
+            1 => queries.remove(0),
+            _ => combine(queries, Occur::Should),
+        })
+    }
+
+    fn boost_terms(&self, query: Box<dyn Query>) -> Box<dyn Query> {
+        let Some((boosted, boost)) = self.boost else {
+            return query;
+        };
+        if let Some(boolean) = query.downcast_ref::<BooleanQuery>() {
+            let clauses = boolean
+                .clauses()
+                .iter()
+                .map(|(occur, clause)| (*occur, self.boost_terms(clause.box_clone())))
+                .collect();
+            return Box::new(BooleanQuery::new(clauses));
+        }
+
+        let field = query
+            .downcast_ref::<TermQuery>()
+            .map(|term| term.term().field())
+            .or_else(|| query.downcast_ref::<PhraseQuery>().map(PhraseQuery::field));
+        match field {
+            Some(field) if field == boosted => Box::new(BoostQuery::new(query, boost)),
+            _ => query,
+        }
+    }
+}
+
+fn combine(queries: Vec<Box<dyn Query>>, occur: Occur) -> Box<dyn Query> {
+    Box::new(BooleanQuery::new(
+        queries.into_iter().map(|query| (occur, query)).collect(),
+    ))
+}
+
+fn split_leaves(parts: &[QueryPart]) -> Vec<Vec<QueryPart>> {
+    let mut leaves = Vec::new();
+    let mut leaf: Vec<QueryPart> = Vec::new();
+    let (mut depth, mut quoted) = (0i32, false);
+    for (position, part) in parts.iter().enumerate() {
+        leaf.push(part.clone());
+        for c in part.text.chars() {
+            match c {
+                '"' => quoted = !quoted,
+                '(' | '[' | '{' if !quoted => depth += 1,
+                ')' | ']' | '}' if !quoted => depth -= 1,
+                _ => {}
+            }
+        }
+        let continues = quoted
+            || depth > 0
+            || part.text == "NOT"
+            || part.text == "IN"
+            || parts
+                .get(position + 1)
+                .is_some_and(|next| next.text == "IN");
+        if !continues {
+            leaves.push(std::mem::take(&mut leaf));
+        }
+    }
+    if !leaf.is_empty() {
+        leaves.push(leaf);
+    }
+    leaves
+}
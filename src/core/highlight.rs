@@ -0,0 +1,136 @@
+use crate::common::error::EngineError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tantivy::query::Query;
+use tantivy::schema::{Field, FieldType, Schema};
+use tantivy::{Document as TantivyDoc, Searcher, Snippet, SnippetGenerator};
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightOptions {
+    pub fields: Vec<String>,
+    pub fragment_size: usize,
+    pub number_of_fragments: usize,
+    pub pre_tag: String,
+    pub post_tag: String,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        HighlightOptions {
+            fields: vec!["content".to_string()],
+            fragment_size: 150,
+            number_of_fragments: 1,
+            pre_tag: "<em>".to_string(),
+            post_tag: "</em>".to_string(),
+        }
+    }
+}
+
+pub struct Highlighter {
+    generators: Vec<(String, Field, SnippetGenerator)>,
+    options: HighlightOptions,
+}
+
+impl Highlighter {
+    pub fn new(
+        searcher: &Searcher,
+        schema: &Schema,
+        query: &dyn Query,
+        options: &HighlightOptions,
+    ) -> Result<Self, EngineError> {
+        if options.fragment_size == 0 || options.number_of_fragments == 0 {
+            return Err(EngineError::InvalidQuery(
+                "fragment_size and number_of_fragments must be positive".to_string(),
+            ));
+        }
+
+        let mut generators = Vec::new();
+        for name in &options.fields {
+            let field = schema
+                .get_field(name)
+                .filter(|field| {
+                    matches!(
+                        schema.get_field_entry(*field).field_type(),
+                        FieldType::Str(_)
+                    )
+                })
+                .ok_or_else(|| {
+                    EngineError::InvalidQuery(format!("cannot highlight field '{}'", name))
+                })?;
+
+            let mut generator = SnippetGenerator::create(searcher, query, field)
+                .map_err(|e| EngineError::InvalidQuery(e.to_string()))?;
+            generator.set_max_num_chars(options.fragment_size);
+            generators.push((name.clone(), field, generator));
+        }
+
+        Ok(Highlighter {
+            generators,
+            options: options.clone(),
+        })
+    }
+
+    pub fn highlight(&self, doc: &TantivyDoc) -> BTreeMap<String, Vec<String>> {
+        let mut highlights = BTreeMap::new();
+        for (name, field, generator) in &self.generators {
+            let text = doc
+                .get_all(*field)
+                .filter_map(|value| value.as_text())
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            let fragments = self.fragments(generator, &text);
+            if !fragments.is_empty() {
+                highlights.insert(name.clone(), fragments);
+            }
+        }
+        highlights
+    }
+
+    fn fragments(&self, generator: &SnippetGenerator, text: &str) -> Vec<String> {
+        let mut segments = vec![text];
+        let mut fragments = Vec::new();
+
+        while fragments.len() < self.options.number_of_fragments {
+            let best = segments
+                .iter()
+                .enumerate()
+                .map(|(position, segment)| (position, generator.snippet(segment)))
+                .filter(|(_, snippet)| !snippet.highlighted().is_empty())
+                .max_by_key(|(position, snippet)| {
+                    (snippet.highlighted().len(), std::cmp::Reverse(*position))
+                });
+            let Some((position, snippet)) = best else {
+                break;
+            };
+
+            let segment = segments.remove(position);
+            let start = segment.find(snippet.fragment()).unwrap_or(0);
+            let end = start + snippet.fragment().len();
+            segments.insert(position, &segment[end..]);
+            segments.insert(position, &segment[..start]);
+
+            fragments.push(self.render(&snippet));
+        }
+
+        fragments
+    }
+
+    fn render(&self, snippet: &Snippet) -> String {
+        let fragment = snippet.fragment();
+        let mut rendered = String::with_capacity(fragment.len());
+        let mut cursor = 0;
+
+        for range in snippet.highlighted() {
+            rendered.push_str(&fragment[cursor..range.start]);
+            rendered.push_str(&self.options.pre_tag);
+            rendered.push_str(&fragment[range.clone()]);
+            rendered.push_str(&self.options.post_tag);
+            cursor = range.end;
+        }
+        rendered.push_str(&fragment[cursor..]);
+
+        rendered.trim().to_string()
+    }
+}
@@ -1,156 +1,745 @@
+use super::aggregation::Aggregator;
+use super::analysis;
+use super::bulk::BulkOperation;
+use super::date_math;
 use super::document::Document;
+use super::dsl::QueryDsl;
+use super::fuzzy::{FuzzyQueryBuilder, QueryPart};
+use super::highlight::Highlighter;
+use super::language::{Language, LANGUAGE_BOOST};
+use super::mapping::{keyword_field, words_field, FieldKind, FieldMapping, Mapping};
+use super::query::{IndexHit, IndexPage, SearchOptions};
+use super::sort::Sorter;
+use super::spelling;
+use super::suggest::{self, SuggestRequest, Suggestion};
+use super::value::to_tantivy_value;
+use crate::common::config::IndexingConfig;
+use crate::common::error::EngineError;
+use crate::storage::persistence;
 use anyhow::Result;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tantivy::{
-    schema::{Schema, STORED, TEXT},
-    Document as TantivyDoc, Index, IndexWriter,
+    collector::{Collector, Count, SegmentCollector, TopDocs},
+    query::{Query, QueryParser},
+    schema::{FieldType, Schema},
+    DocId, Document as TantivyDoc, Index, IndexReader, IndexWriter, ReloadPolicy, Score,
+    SegmentOrdinal, SegmentReader, Term,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
 
 pub struct SearchIndex {
+    path: PathBuf,
+    current: std::sync::RwLock<Arc<Generation>>,
+    pending: AtomicUsize,
+    max_buffered_docs: usize,
+    refreshed: watch::Sender<u64>,
+}
+
+struct Generation {
     index: Index,
-    writer: Arc<RwLock<IndexWriter>>,
+    reader: IndexReader,
+    writer: RwLock<IndexWriter>,
     schema: Schema,
+    mapping: Mapping,
 }
 
-impl SearchIndex {
-    pub fn new(index_path: &str) -> Result<Self> {
-        let mut schema_builder = Schema::builder();
-        let _id_field = schema_builder.add_text_field("id", TEXT | STORED);
-        let _content_field = schema_builder.add_text_field("content", TEXT | STORED);
-        let _author_field = schema_builder.add_text_field("author", TEXT | STORED);
-        let _type_field = schema_builder.add_text_field("type", TEXT | STORED);
-        let _category_field = schema_builder.add_text_field("category", TEXT | STORED);
-        let schema = schema_builder.build();
-
-        fs::create_dir_all(index_path)?;
-
-        let index = match Index::open_in_dir(index_path) {
-            Ok(index) => index,
-            Err(_) => Index::create_in_dir(index_path, schema.clone())?,
+impl Generation {
+    fn directory(path: &Path, generation: u64) -> PathBuf {
+        path.join(format!("gen-{}", generation))
+    }
+
+    fn open(path: &Path, mapping: Mapping) -> Result<Self> {
+        let schema = mapping.build_schema();
+        let directory = Self::directory(path, mapping.generation);
+        fs::create_dir_all(&directory)?;
+
+        let index = match Index::open_in_dir(&directory) {
+            Ok(index) if index.schema() == schema => index,
+            Ok(_) => {
+                tracing::warn!(
+                    "Index schema changed, recreating index at {}",
+                    directory.display()
+                );
+                fs::remove_dir_all(&directory)?;
+                fs::create_dir_all(&directory)?;
+                Index::create_in_dir(&directory, schema.clone())?
+            }
+            Err(_) => Index::create_in_dir(&directory, schema.clone())?,
         };
+        analysis::register(&index);
 
         let writer = index.writer(50_000_000)?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
 
-        Ok(SearchIndex {
+        Ok(Generation {
             index,
-            writer: Arc::new(RwLock::new(writer)),
+            reader,
+            writer: RwLock::new(writer),
             schema,
+            mapping,
         })
     }
 
-    pub async fn add_document(&self, doc: &Document) -> Result<()> {
+    fn id_term(&self, id: &str) -> Term {
+        Term::from_field_text(self.schema.get_field("id").unwrap(), id)
+    }
+
+    fn to_tantivy_doc(&self, doc: &Document) -> TantivyDoc {
         let mut tantivy_doc = TantivyDoc::new();
         let id_field = self.schema.get_field("id").unwrap();
         let content_field = self.schema.get_field("content").unwrap();
 
         tantivy_doc.add_text(id_field, &doc.id);
-        tantivy_doc.add_text(content_field, &doc.content);
+        if !doc.content.is_empty() {
+            tantivy_doc.add_text(content_field, &doc.content);
+            if let Some(words) = self.schema.get_field(&words_field("content")) {
+                tantivy_doc.add_text(words, &doc.content);
+            }
+            if let Some(language) = Language::of_document(doc) {
+                let language_field = self.schema.get_field(&language.field()).unwrap();
+                tantivy_doc.add_text(language_field, &doc.content);
+            }
+        }
+        tantivy_doc.add_u64(self.schema.get_field("_seq_no").unwrap(), doc.seq_no);
 
         for (key, value) in &doc.metadata {
-            if let Some(field) = self.schema.get_field(key) {
-                tantivy_doc.add_text(field, value);
-            }
+            self.add_metadata_value(&mut tantivy_doc, key, value);
         }
 
-        let mut writer = self.writer.write().await;
-        writer.add_document(tantivy_doc)?;
-        writer.commit()?;
+        let field_names_field = self.schema.get_field("_field_names").unwrap();
+        let field_names: BTreeSet<&str> = tantivy_doc
+            .field_values()
+            .iter()
+            .map(|field_value| self.schema.get_field_name(field_value.field()))
+            .filter(|name| !name.starts_with('_'))
+            .collect();
+        for name in field_names {
+            tantivy_doc.add_text(field_names_field, name);
+        }
 
-        Ok(())
+        tantivy_doc
     }
 
-    pub fn search(&self, query: &str) -> Result<Vec<String>> {
-        let reader = self.index.reader()?;
-        let searcher = reader.searcher();
+    fn add_metadata_value(&self, tantivy_doc: &mut TantivyDoc, key: &str, value: &Value) {
+        match value {
+            Value::Null => {}
+            Value::Array(values) => {
+                for value in values {
+                    self.add_metadata_value(tantivy_doc, key, value);
+                }
+            }
+            Value::Object(object) => {
+                for (child, value) in object {
+                    self.add_metadata_value(tantivy_doc, &format!("{}.{}", key, child), value);
+                }
+            }
+            _ => {
+                let Some(field) = self.schema.get_field(key) else {
+                    return;
+                };
+                let field_type = self.schema.get_field_entry(field).field_type();
+                match to_tantivy_value(field_type, value) {
+                    Some(value) => tantivy_doc.add_field_value(field, value),
+                    None => tracing::warn!(
+                        "Skipping metadata value {} for field {} of type {:?}",
+                        value,
+                        key,
+                        field_type.value_type()
+                    ),
+                }
+                for name in [keyword_field(key), words_field(key)] {
+                    let Some(companion) = self.schema.get_field(&name) else {
+                        continue;
+                    };
+                    let field_type = self.schema.get_field_entry(companion).field_type();
+                    if let Some(value) = to_tantivy_value(field_type, value) {
+                        tantivy_doc.add_field_value(companion, value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn parse(&self, query: &str, options: &SearchOptions) -> Result<Box<dyn Query>> {
+        let schema = &self.schema;
 
         let mut query_parts = Vec::new();
-        let mut search_fields = vec![self.schema.get_field("content").unwrap()];
+        let mut search_fields = vec![schema.get_field("content").unwrap()];
+        search_fields
+            .extend(Language::ALL.map(|language| schema.get_field(&language.field()).unwrap()));
+        let mut free_text = Vec::new();
+        let mut open_range: Option<bool> = None;
+        let mut in_phrase = false;
 
         for part in query.split_whitespace() {
-            if let Some((field, value)) = part.split_once(':') {
-                if let Some(schema_field) = self.schema.get_field(field) {
-                    let field_query = format!("{}:{}", field, value);
-                    query_parts.push(field_query);
-                    search_fields.push(schema_field);
+            let quoted = part.matches('"').count() % 2 == 1;
+            if in_phrase {
+                free_text.push(part);
+                query_parts.push(QueryPart::new(part.to_string()));
+            } else if let Some(is_date) = open_range {
+                let part = if is_date {
+                    date_math::rewrite_bound(part)?
+                } else {
+                    part.to_string()
+                };
+                if part.ends_with([']', '}']) {
+                    open_range = None;
+                }
+                query_parts.push(QueryPart::new(part));
+            } else if let Some((field, value)) = part
+                .split_once(':')
+                .filter(|(field, _)| !field.starts_with('"'))
+            {
+                if let Some(schema_field) = schema.get_field(field) {
+                    let field_type = schema.get_field_entry(schema_field).field_type();
+                    let is_date = matches!(field_type, FieldType::Date(_));
+                    let value = match field_type {
+                        FieldType::Date(_) => date_math::rewrite_bound(value)?,
+                        _ => value.to_string(),
+                    };
+                    if value.starts_with(['[', '{']) && !value.ends_with([']', '}']) {
+                        open_range = Some(is_date);
+                    }
+                    let text = format!("{}:{}", field, value);
+                    if let FieldType::Str(_) = field_type {
+                        query_parts.push(QueryPart::term(text, Some(schema_field), &value));
+                        search_fields.push(schema_field);
+                    } else {
+                        query_parts.push(QueryPart::new(text));
+                    }
                 }
             } else {
-                query_parts.push(part.to_string());
+                if !["AND", "OR", "NOT"].contains(&part) {
+                    free_text.push(part);
+                }
+                query_parts.push(QueryPart::term(part.to_string(), None, part));
+            }
+            if quoted {
+                in_phrase = !in_phrase;
             }
         }
 
-        let query_str = query_parts.join(" ");
-        let query_parser = tantivy::query::QueryParser::for_index(&self.index, search_fields);
-        let query = query_parser.parse_query(&query_str)?;
+        let boost = Language::detect(&free_text.join(" "))
+            .map(|language| (schema.get_field(&language.field()).unwrap(), LANGUAGE_BOOST));
+        let parser = QueryParser::for_index(&self.index, search_fields.clone());
 
-        let top_docs = searcher.search(&query, &tantivy::collector::TopDocs::with_limit(10))?;
+        FuzzyQueryBuilder {
+            index: &self.index,
+            parser: &parser,
+            fields: &search_fields,
+            prefix_length: options.fuzzy_prefix_length,
+            boost,
+        }
+        .build(&query_parts)
+    }
+
+    fn collect(&self, query: &dyn Query, options: &SearchOptions) -> Result<IndexPage> {
+        options.validate()?;
+        let window = options.window()?;
+        let searcher = self.reader.searcher();
+        let id_field = self.schema.get_field("id").unwrap();
+        let sorter = Sorter::new(&self.schema, &options.sort)?;
+        let after = options
+            .search_after
+            .as_ref()
+            .map(|cursor| sorter.cursor(&cursor.0))
+            .transpose()?;
+        let min_score = options.min_score.unwrap_or(f32::MIN);
+        let aggregator = Aggregator::new(&self.schema, &options.aggs, min_score)?;
+
+        let segment_sorters = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment_reader| {
+                let segment_sorter = sorter.for_segment(segment_reader).map_err(|e| {
+                    EngineError::InvalidQuery(format!("cannot sort segment: {}", e))
+                })?;
+                Ok((segment_reader.segment_id(), Arc::new(segment_sorter)))
+            })
+            .collect::<Result<HashMap<_, _>, EngineError>>()?;
+
+        let top_docs = TopDocs::with_limit(window.max(1)).tweak_score(
+            move |segment_reader: &SegmentReader| {
+                let segment_sorter = segment_sorters.get(&segment_reader.segment_id()).cloned();
+                let after = after.clone();
+                move |doc, score| {
+                    let key = segment_sorter.as_ref()?.key(doc, score);
+                    match &after {
+                        _ if score < min_score => None,
+                        Some(after) if key >= *after => None,
+                        _ => Some((key, score)),
+                    }
+                }
+            },
+        );
+        let ((total, max_score), top_docs, partials) = searcher.search(
+            query,
+            &(HitStats { min_score }, top_docs, aggregator.clone()),
+        )?;
+        let aggregations = aggregator.finish(partials)?;
+        let highlighter = options
+            .highlight
+            .as_ref()
+            .map(|highlight| Highlighter::new(&searcher, &self.schema, query, highlight))
+            .transpose()?;
 
-        let mut results = Vec::new();
-        for (_score, doc_address) in top_docs {
+        let mut hits = Vec::new();
+        for (key, doc_address) in top_docs.into_iter().skip(options.from).take(options.size) {
+            let Some((key, score)) = key else {
+                continue;
+            };
             let retrieved_doc = searcher.doc(doc_address)?;
-            let id_field = self.schema.get_field("id").unwrap();
-            if let Some(id) = retrieved_doc.get_first(id_field) {
-                results.push(id.as_text().unwrap().to_string());
+            if let Some(id) = retrieved_doc
+                .get_first(id_field)
+                .and_then(|id| id.as_text())
+            {
+                hits.push(IndexHit {
+                    id: id.to_string(),
+                    score,
+                    sort: key.values(),
+                    highlight: highlighter
+                        .as_ref()
+                        .map(|highlighter| highlighter.highlight(&retrieved_doc))
+                        .unwrap_or_default(),
+                });
             }
         }
 
-        Ok(results)
+        Ok(IndexPage {
+            total,
+            max_score,
+            hits,
+            aggregations,
+            suggestions: Vec::new(),
+        })
     }
+}
 
-    pub async fn close(&self) -> Result<()> {
-        let mut writer = self.writer.write().await;
-        writer.commit()?;
-        Ok(())
+struct HitStats {
+    min_score: Score,
+}
+
+struct SegmentHitStats {
+    min_score: Score,
+    count: usize,
+    max_score: Option<Score>,
+}
+
+impl Collector for HitStats {
+    type Fruit = (usize, Option<Score>);
+    type Child = SegmentHitStats;
+
+    fn for_segment(
+        &self,
+        _segment_ord: SegmentOrdinal,
+        _segment: &SegmentReader,
+    ) -> tantivy::Result<SegmentHitStats> {
+        Ok(SegmentHitStats {
+            min_score: self.min_score,
+            count: 0,
+            max_score: None,
+        })
     }
 
-    pub fn search_with_metadata(&self, query: &str, fields: &[&str]) -> Result<Vec<String>> {
-        let reader = self.index.reader()?;
-        let searcher = reader.searcher();
-        let content_field = self.schema.get_field("content").unwrap();
+    fn requires_scoring(&self) -> bool {
+        true
+    }
 
-        let mut search_fields = vec![content_field];
-        for field in fields {
-            if let Some(field) = self.schema.get_field(field) {
-                search_fields.push(field);
+    fn merge_fruits(&self, fruits: Vec<(usize, Option<Score>)>) -> tantivy::Result<Self::Fruit> {
+        Ok(fruits.into_iter().fold(
+            (0, None),
+            |(count, max_score), (segment_count, segment_max)| {
+                let max_score = match (max_score, segment_max) {
+                    (Some(max), Some(segment_max)) => Some(f32::max(max, segment_max)),
+                    (max, segment_max) => max.or(segment_max),
+                };
+                (count + segment_count, max_score)
+            },
+        ))
+    }
+}
+
+impl SegmentCollector for SegmentHitStats {
+    type Fruit = (usize, Option<Score>);
+
+    fn collect(&mut self, _doc: DocId, score: Score) {
+        if score < self.min_score {
+            return;
+        }
+        self.count += 1;
+        self.max_score = Some(self.max_score.map_or(score, |max| max.max(score)));
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        (self.count, self.max_score)
+    }
+}
+
+impl SearchIndex {
+    pub fn new(index_path: &str, indexing: &IndexingConfig) -> Result<Self> {
+        let path = PathBuf::from(index_path);
+        fs::create_dir_all(&path)?;
+        remove_legacy_layout(&path)?;
+
+        let mapping_path = path.join("mapping.json");
+        let stored = persistence::load_mapping(&mapping_path)?;
+        let mapping = stored.clone().unwrap_or_default().configure(indexing)?;
+        if stored.as_ref() != Some(&mapping) {
+            persistence::save_mapping(&mapping, &mapping_path)?;
+        }
+
+        let generation = Generation::open(&path, mapping)?;
+
+        Ok(SearchIndex {
+            path,
+            current: std::sync::RwLock::new(Arc::new(generation)),
+            pending: AtomicUsize::new(0),
+            max_buffered_docs: indexing.max_buffered_docs,
+            refreshed: watch::channel(0).0,
+        })
+    }
+
+    fn generation(&self) -> Arc<Generation> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn mapping(&self) -> Mapping {
+        self.generation().mapping.clone()
+    }
+
+    pub fn validate(&self, doc: &Document) -> Result<(), EngineError> {
+        self.generation().mapping.validate_document(doc)
+    }
+
+    pub async fn add_document(&self, doc: &Document) -> Result<()> {
+        let generation = self.generation();
+        let tantivy_doc = generation.to_tantivy_doc(doc);
+
+        let mut writer = generation.writer.write().await;
+        writer.delete_term(generation.id_term(&doc.id));
+        writer.add_document(tantivy_doc)?;
+        self.buffer(&generation, &mut writer, 1)
+    }
+
+    pub async fn apply_bulk(&self, operations: &[BulkOperation]) -> Result<()> {
+        let generation = self.generation();
+
+        let mut writer = generation.writer.write().await;
+        for operation in operations {
+            match operation {
+                BulkOperation::Index { doc, .. } => {
+                    writer.delete_term(generation.id_term(&doc.id));
+                    writer.add_document(generation.to_tantivy_doc(doc))?;
+                }
+                BulkOperation::Delete { id, .. } => {
+                    writer.delete_term(generation.id_term(id));
+                }
+                BulkOperation::Invalid { .. } => {}
             }
         }
+        self.buffer(&generation, &mut writer, operations.len())
+    }
 
-        let query_parser = tantivy::query::QueryParser::for_index(&self.index, search_fields);
-        let query = query_parser.parse_query(query)?;
+    pub fn reindex<'a>(&self, docs: impl IntoIterator<Item = &'a Document>) -> Result<()> {
+        let generation = self.generation();
+        let mut writer = generation
+            .writer
+            .try_write()
+            .map_err(|_| anyhow::anyhow!("Index writer is busy"))?;
 
-        let top_docs = searcher.search(&query, &tantivy::collector::TopDocs::with_limit(10))?;
+        writer.delete_all_documents()?;
+        for doc in docs {
+            writer.add_document(generation.to_tantivy_doc(doc))?;
+        }
+        self.commit(&generation, &mut writer)
+    }
 
-        let mut results = Vec::new();
-        for (_score, doc_address) in top_docs {
-            let retrieved_doc = searcher.doc(doc_address)?;
-            let id_field = self.schema.get_field("id").unwrap();
-            if let Some(id) = retrieved_doc.get_first(id_field) {
-                results.push(id.as_text().unwrap().to_string());
+    pub fn reconcile<'a>(&self, docs: impl IntoIterator<Item = &'a Document>) -> Result<()> {
+        let docs: Vec<&Document> = docs.into_iter().collect();
+        let generation = self.generation();
+        let seq_no = generation.schema.get_field("_seq_no").unwrap();
+        let searcher = generation.reader.searcher();
+        let mut committed = 0;
+        for segment_reader in searcher.segment_readers() {
+            committed = committed.max(segment_reader.fast_fields().u64(seq_no)?.max_value());
+        }
+
+        let stale: Vec<&Document> = docs
+            .iter()
+            .copied()
+            .filter(|doc| doc.seq_no > committed)
+            .collect();
+        if !stale.is_empty() {
+            tracing::info!("Reindexing {} uncommitted documents", stale.len());
+            let mut writer = generation
+                .writer
+                .try_write()
+                .map_err(|_| anyhow::anyhow!("Index writer is busy"))?;
+            for doc in stale {
+                writer.delete_term(generation.id_term(&doc.id));
+                writer.add_document(generation.to_tantivy_doc(doc))?;
             }
+            self.commit(&generation, &mut writer)?;
+        }
+
+        if self.num_docs()? != docs.len() as u64 {
+            tracing::info!("Reindexing {} stored documents", docs.len());
+            self.reindex(docs)?;
         }
+        Ok(())
+    }
 
-        Ok(results)
+    pub fn ensure_mapped<'a>(
+        &self,
+        incoming: impl IntoIterator<Item = &'a Document>,
+        existing: impl IntoIterator<Item = &'a Document>,
+    ) -> Result<bool> {
+        let Some(mapping) = self.dynamic_mapping(incoming) else {
+            return Ok(false);
+        };
+        self.rebuild(mapping, existing)?;
+        Ok(true)
     }
 
-    pub async fn add_metadata_field(&self, field_name: &str) -> Result<()> {
-        let mut writer = self.writer.write().await;
-        let mut schema_builder = Schema::builder();
+    pub async fn ensure_mapped_in_background<'a>(
+        self: &Arc<Self>,
+        incoming: impl IntoIterator<Item = &'a Document>,
+        existing: impl IntoIterator<Item = &'a Document>,
+    ) -> Result<bool> {
+        let Some(mapping) = self.dynamic_mapping(incoming) else {
+            return Ok(false);
+        };
+        self.rebuild_in_background(mapping, existing.into_iter().cloned().collect())
+            .await?;
+        Ok(true)
+    }
 
-        for (_field, field_entry) in self.schema.fields() {
-            schema_builder.add_field(field_entry.clone());
+    fn dynamic_mapping<'a>(
+        &self,
+        incoming: impl IntoIterator<Item = &'a Document>,
+    ) -> Option<Mapping> {
+        let mut mapping = self.mapping();
+        let new_fields = mapping.unmapped_fields(incoming);
+        if new_fields.is_empty() {
+            return None;
         }
 
-        schema_builder.add_text_field(field_name, TEXT | STORED);
-        schema_builder.build();
+        tracing::info!(
+            "Adding dynamic metadata fields: {:?}",
+            new_fields.keys().collect::<Vec<_>>()
+        );
+        mapping.properties.extend(new_fields);
+        Some(mapping)
+    }
+
+    pub async fn rebuild_in_background(
+        self: &Arc<Self>,
+        mapping: Mapping,
+        docs: Vec<Document>,
+    ) -> Result<()> {
+        let search_index = Arc::clone(self);
+        tokio::task::spawn_blocking(move || search_index.rebuild(mapping, &docs)).await?
+    }
+
+    pub fn rebuild<'a>(
+        &self,
+        mut mapping: Mapping,
+        docs: impl IntoIterator<Item = &'a Document>,
+    ) -> Result<()> {
+        let previous = self.generation();
+        mapping.generation = previous.mapping.generation + 1;
+
+        let directory = Generation::directory(&self.path, mapping.generation);
+        if directory.exists() {
+            fs::remove_dir_all(&directory)?;
+        }
+
+        let generation = Generation::open(&self.path, mapping)?;
+        {
+            let mut writer = generation
+                .writer
+                .try_write()
+                .map_err(|_| anyhow::anyhow!("Index writer is busy"))?;
+            for doc in docs {
+                writer.add_document(generation.to_tantivy_doc(doc))?;
+            }
+            writer.commit()?;
+            generation.reader.reload()?;
+        }
+
+        persistence::save_mapping(&generation.mapping, &self.path.join("mapping.json"))?;
+        tracing::info!(
+            "Switched to index generation {}",
+            generation.mapping.generation
+        );
+
+        *self.current.write().unwrap() = Arc::new(generation);
+        self.pending.store(0, Ordering::SeqCst);
+        self.refreshed.send_modify(|generation| *generation += 1);
+
+        let previous_directory = Generation::directory(&self.path, previous.mapping.generation);
+        drop(previous);
+        if let Err(e) = fs::remove_dir_all(&previous_directory) {
+            tracing::warn!(
+                "Failed to remove old index generation {}: {}",
+                previous_directory.display(),
+                e
+            );
+        }
 
-        writer.commit()?;
         Ok(())
     }
 
-    pub async fn update_schema(&self) -> Result<()> {
-        let mut writer = self.writer.write().await;
+    pub async fn delete_document(&self, id: &str) -> Result<()> {
+        let generation = self.generation();
+
+        let mut writer = generation.writer.write().await;
+        writer.delete_term(generation.id_term(id));
+        self.buffer(&generation, &mut writer, 1)
+    }
+
+    fn buffer(
+        &self,
+        generation: &Generation,
+        writer: &mut IndexWriter,
+        count: usize,
+    ) -> Result<()> {
+        let pending = self.pending.fetch_add(count, Ordering::SeqCst) + count;
+        if pending >= self.max_buffered_docs {
+            self.commit(generation, writer)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&self, generation: &Generation, writer: &mut IndexWriter) -> Result<()> {
         writer.commit()?;
+        self.pending.store(0, Ordering::SeqCst);
+        generation.reader.reload()?;
+        self.refreshed.send_modify(|generation| *generation += 1);
         Ok(())
     }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) > 0
+    }
+
+    pub async fn refresh(&self) -> Result<()> {
+        let generation = self.generation();
+        let mut writer = generation.writer.write().await;
+        self.commit(&generation, &mut writer)
+    }
+
+    pub async fn wait_for_refresh(&self) {
+        let mut refreshed = self.refreshed.subscribe();
+        if self.has_pending() {
+            let _ = refreshed.changed().await;
+        }
+    }
+
+    pub fn num_docs(&self) -> Result<u64> {
+        Ok(self.generation().reader.searcher().num_docs())
+    }
+
+    pub fn search(&self, query: &str, options: &SearchOptions) -> Result<IndexPage> {
+        let generation = self.generation();
+        let parsed = generation.parse(query, options)?;
+        let mut page = generation.collect(parsed.as_ref(), options)?;
+
+        if options.suggest && page.total == 0 {
+            let searcher = generation.reader.searcher();
+            page.suggestions =
+                spelling::corrections(&generation.index, &searcher, query, |corrected| {
+                    let corrected = generation.parse(corrected, options)?;
+                    Ok(searcher.search(corrected.as_ref(), &Count)? as u64)
+                })?;
+        }
+
+        Ok(page)
+    }
+
+    pub fn search_dsl(&self, query: &QueryDsl, options: &SearchOptions) -> Result<IndexPage> {
+        let generation = self.generation();
+        let query = query.compile(&generation.index)?;
+        generation.collect(query.as_ref(), options)
+    }
+
+    pub fn suggest(&self, request: &SuggestRequest) -> Result<Vec<Suggestion>> {
+        let generation = self.generation();
+        let searcher = generation.reader.searcher();
+        suggest::suggest(&generation.index, &searcher, request)
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.refresh().await
+    }
+
+    pub fn search_with_metadata(
+        &self,
+        query: &str,
+        fields: &[&str],
+        options: &SearchOptions,
+    ) -> Result<IndexPage> {
+        let generation = self.generation();
+        let content_field = generation.schema.get_field("content").unwrap();
+
+        let mut search_fields = vec![content_field];
+        for field in fields {
+            if let Some(field) = generation.schema.get_field(field) {
+                search_fields.push(field);
+            }
+        }
+
+        let query_parser = QueryParser::for_index(&generation.index, search_fields);
+        let query = query_parser.parse_query(query)?;
+
+        generation.collect(query.as_ref(), options)
+    }
+
+    pub async fn add_metadata_field<'a>(
+        self: &Arc<Self>,
+        field_name: &str,
+        docs: impl IntoIterator<Item = &'a Document>,
+    ) -> Result<()> {
+        let mut mapping = self.mapping();
+        if mapping.properties.contains_key(field_name) {
+            return Ok(());
+        }
+
+        mapping
+            .properties
+            .insert(field_name.to_string(), FieldMapping::new(FieldKind::Text));
+        self.rebuild_in_background(mapping, docs.into_iter().cloned().collect())
+            .await
+    }
+
+    pub async fn update_schema(&self) -> Result<()> {
+        self.refresh().await
+    }
+}
+
+fn remove_legacy_layout(path: &Path) -> Result<()> {
+    if !path.join("meta.json").exists() {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        "Removing single-generation index files from {}",
+        path.display()
+    );
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && entry.file_name() != "mapping.json" {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,70 @@
+use super::document::Document;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const LANG_KEY: &str = "lang";
+pub const LANGUAGE_BOOST: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Ru,
+    En,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::Ru, Language::En];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::Ru => "ru",
+            Language::En => "en",
+        }
+    }
+
+    pub fn analyzer(self) -> &'static str {
+        match self {
+            Language::Ru => "russian",
+            Language::En => "en_stem",
+        }
+    }
+
+    pub fn field(self) -> String {
+        format!("_content_{}", self.code())
+    }
+
+    pub fn detect(text: &str) -> Option<Language> {
+        let (cyrillic, latin) =
+            text.chars()
+                .fold((0usize, 0usize), |(cyrillic, latin), c| match c {
+                    'а'..='я' | 'А'..='Я' | 'ё' | 'Ё' => (cyrillic + 1, latin),
+                    c if c.is_ascii_alphabetic() => (cyrillic, latin + 1),
+                    _ => (cyrillic, latin),
+                });
+        match (cyrillic, latin) {
+            (0, 0) => None,
+            (cyrillic, latin) if cyrillic >= latin => Some(Language::Ru),
+            _ => Some(Language::En),
+        }
+    }
+
+    pub fn of_document(doc: &Document) -> Option<Language> {
+        doc.metadata
+            .get(LANG_KEY)
+            .and_then(|lang| lang.as_str())
+            .and_then(|lang| lang.parse().ok())
+            .or_else(|| Language::detect(&doc.content))
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "ru" | "rus" | "russian" => Ok(Language::Ru),
+            "en" | "eng" | "english" => Ok(Language::En),
+            _ => Err(format!("unsupported language '{}'", value)),
+        }
+    }
+}
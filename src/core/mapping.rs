@@ -0,0 +1,413 @@
+use super::analysis;
+use super::document::Document;
+use super::language::Language;
+use super::value::{date_from_millis, parse_date};
+use crate::common::config::IndexingConfig;
+use crate::common::error::EngineError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tantivy::schema::{
+    Cardinality, DateOptions, Facet, FacetOptions, IndexRecordOption, NumericOptions, Schema,
+    TextFieldIndexing, TextOptions,
+};
+use tantivy::DatePrecision;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    Text,
+    Keyword,
+    #[serde(alias = "integer")]
+    Long,
+    #[serde(alias = "float")]
+    Double,
+    #[serde(alias = "bool")]
+    Boolean,
+    Date,
+    Facet,
+}
+
+impl FieldKind {
+    pub fn accepts(self, value: &Value) -> bool {
+        match (self, value) {
+            (_, Value::Null) => true,
+            (_, Value::Array(values)) => values.iter().all(|value| self.accepts(value)),
+            (FieldKind::Text | FieldKind::Keyword, value) => {
+                value.is_string() || value.is_number() || value.is_boolean()
+            }
+            (FieldKind::Long, value) => value.is_i64(),
+            (FieldKind::Double, value) => value.is_number(),
+            (FieldKind::Boolean, value) => value.is_boolean(),
+            (FieldKind::Date, Value::String(text)) => parse_date(text).is_some(),
+            (FieldKind::Date, value) => value.as_i64().and_then(date_from_millis).is_some(),
+            (FieldKind::Facet, Value::String(path)) => Facet::from_text(path).is_ok(),
+            (FieldKind::Facet, _) => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldMapping {
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analyzer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fast: Option<bool>,
+}
+
+impl FieldMapping {
+    pub fn new(kind: FieldKind) -> Self {
+        FieldMapping {
+            kind,
+            analyzer: None,
+            fast: None,
+        }
+    }
+
+    pub fn is_fast(&self) -> bool {
+        self.fast.unwrap_or(true)
+    }
+
+    fn validate(&self, name: &str) -> Result<(), EngineError> {
+        if let Some(analyzer) = &self.analyzer {
+            if self.kind != FieldKind::Text {
+                return Err(EngineError::InvalidMapping(format!(
+                    "analyzer is only supported on text fields, '{}' is {:?}",
+                    name, self.kind
+                )));
+            }
+            if !analysis::is_known(analyzer) {
+                return Err(EngineError::InvalidMapping(format!(
+                    "unknown analyzer '{}' for field '{}'",
+                    analyzer, name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mapping {
+    #[serde(default)]
+    pub generation: u64,
+    #[serde(default = "default_dynamic")]
+    pub dynamic: bool,
+    #[serde(default = "default_analyzer")]
+    pub analyzer: String,
+    #[serde(default)]
+    pub properties: BTreeMap<String, FieldMapping>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MappingView<'a> {
+    pub dynamic: bool,
+    pub analyzer: &'a str,
+    pub properties: &'a BTreeMap<String, FieldMapping>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingUpdate {
+    pub dynamic: Option<bool>,
+    #[serde(default)]
+    pub properties: BTreeMap<String, FieldMapping>,
+}
+
+fn default_dynamic() -> bool {
+    true
+}
+
+fn default_analyzer() -> String {
+    "default".to_string()
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        let properties = ["author", "type", "category"]
+            .into_iter()
+            .map(|name| (name.to_string(), FieldMapping::new(FieldKind::Text)))
+            .collect();
+
+        Mapping {
+            generation: 0,
+            dynamic: true,
+            analyzer: default_analyzer(),
+            properties,
+        }
+    }
+}
+
+impl Mapping {
+    pub fn view(&self) -> MappingView<'_> {
+        MappingView {
+            dynamic: self.dynamic,
+            analyzer: &self.analyzer,
+            properties: &self.properties,
+        }
+    }
+
+    pub fn build_schema(&self) -> Schema {
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("id", text_options("raw", IndexRecordOption::Basic));
+        schema_builder.add_text_field(
+            "content",
+            text_options(&self.analyzer, IndexRecordOption::WithFreqsAndPositions),
+        );
+        if analysis::is_stemming(&self.analyzer) {
+            schema_builder.add_text_field(&words_field("content"), words_options());
+        }
+        for language in Language::ALL {
+            schema_builder.add_text_field(
+                &language.field(),
+                TextOptions::default().set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer(language.analyzer())
+                        .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+                ),
+            );
+        }
+        schema_builder.add_u64_field(
+            "_seq_no",
+            NumericOptions::default().set_fast(Cardinality::SingleValue),
+        );
+        schema_builder.add_text_field(
+            "_field_names",
+            TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer("raw")
+                    .set_index_option(IndexRecordOption::Basic),
+            ),
+        );
+
+        for (name, field) in &self.properties {
+            let mut numeric = NumericOptions::default().set_indexed().set_stored();
+            let mut date = DateOptions::default()
+                .set_indexed()
+                .set_stored()
+                .set_precision(DatePrecision::Milliseconds);
+            if field.is_fast() {
+                numeric = numeric.set_fast(Cardinality::MultiValues);
+                date = date.set_fast(Cardinality::MultiValues);
+            }
+
+            match field.kind {
+                FieldKind::Text => {
+                    if field.is_fast() {
+                        schema_builder.add_text_field(
+                            &keyword_field(name),
+                            TextOptions::default()
+                                .set_indexing_options(
+                                    TextFieldIndexing::default()
+                                        .set_tokenizer("raw")
+                                        .set_index_option(IndexRecordOption::Basic),
+                                )
+                                .set_fast(),
+                        );
+                    }
+                    let analyzer = field.analyzer.as_deref().unwrap_or(&self.analyzer);
+                    if analysis::is_stemming(analyzer) {
+                        schema_builder.add_text_field(&words_field(name), words_options());
+                    }
+                    schema_builder.add_text_field(
+                        name,
+                        text_options(analyzer, IndexRecordOption::WithFreqsAndPositions),
+                    )
+                }
+                FieldKind::Keyword => {
+                    let options = text_options("raw", IndexRecordOption::Basic);
+                    let options = if field.is_fast() {
+                        options.set_fast()
+                    } else {
+                        options
+                    };
+                    schema_builder.add_text_field(name, options)
+                }
+                FieldKind::Long => schema_builder.add_i64_field(name, numeric),
+                FieldKind::Double => schema_builder.add_f64_field(name, numeric),
+                FieldKind::Boolean => schema_builder.add_bool_field(name, numeric),
+                FieldKind::Date => schema_builder.add_date_field(name, date),
+                FieldKind::Facet => {
+                    schema_builder.add_facet_field(name, FacetOptions::default().set_stored())
+                }
+            };
+        }
+
+        schema_builder.build()
+    }
+
+    pub fn configure(&self, indexing: &IndexingConfig) -> Result<Mapping, EngineError> {
+        if !analysis::is_known(&indexing.analyzer) {
+            return Err(EngineError::InvalidMapping(format!(
+                "unknown index analyzer '{}'",
+                indexing.analyzer
+            )));
+        }
+
+        let properties = indexing
+            .field_analyzers
+            .iter()
+            .map(|(name, analyzer)| {
+                let field = self
+                    .properties
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| FieldMapping::new(FieldKind::Text));
+                let field = FieldMapping {
+                    analyzer: Some(analyzer.clone()),
+                    ..field
+                };
+                (name.clone(), field)
+            })
+            .collect();
+
+        let mut configured = self.merge(MappingUpdate {
+            dynamic: None,
+            properties,
+        })?;
+        configured.analyzer = indexing.analyzer.clone();
+        Ok(configured)
+    }
+
+    pub fn merge(&self, update: MappingUpdate) -> Result<Mapping, EngineError> {
+        let mut merged = self.clone();
+        if let Some(dynamic) = update.dynamic {
+            merged.dynamic = dynamic;
+        }
+
+        for (name, field) in update.properties {
+            if !is_valid_field_name(&name) || name.starts_with('_') {
+                return Err(EngineError::InvalidMapping(format!(
+                    "invalid field name '{}'",
+                    name
+                )));
+            }
+            field.validate(&name)?;
+            merged.properties.insert(name, field);
+        }
+
+        Ok(merged)
+    }
+
+    pub fn validate_document(&self, doc: &Document) -> Result<(), EngineError> {
+        for (key, value) in &doc.metadata {
+            self.validate_value(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn validate_value(&self, key: &str, value: &Value) -> Result<(), EngineError> {
+        match (self.properties.get(key), value) {
+            (Some(field), value) if !field.kind.accepts(value) => {
+                Err(EngineError::InvalidDocument(format!(
+                    "value {} of field '{}' is not a valid {:?}",
+                    value, key, field.kind
+                )))
+            }
+            (None, Value::Object(object)) => {
+                for (child, value) in object {
+                    self.validate_value(&format!("{}.{}", key, child), value)?;
+                }
+                Ok(())
+            }
+            (None, Value::Array(values)) => {
+                for value in values {
+                    self.validate_value(key, value)?;
+                }
+                Ok(())
+            }
+            (None, _) if !is_valid_field_name(key) => Err(EngineError::InvalidDocument(format!(
+                "invalid metadata field name '{}'",
+                key
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn unmapped_fields<'a>(
+        &self,
+        docs: impl IntoIterator<Item = &'a Document>,
+    ) -> BTreeMap<String, FieldMapping> {
+        let mut fields = BTreeMap::new();
+        if !self.dynamic {
+            return fields;
+        }
+
+        for doc in docs {
+            for (key, value) in &doc.metadata {
+                self.collect_unmapped(key, value, &mut fields);
+            }
+        }
+        fields
+    }
+
+    fn collect_unmapped(
+        &self,
+        key: &str,
+        value: &Value,
+        fields: &mut BTreeMap<String, FieldMapping>,
+    ) {
+        match value {
+            Value::Object(object) => {
+                for (child, value) in object {
+                    self.collect_unmapped(&format!("{}.{}", key, child), value, fields);
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    self.collect_unmapped(key, value, fields);
+                }
+            }
+            _ if key.starts_with('_')
+                || !is_valid_field_name(key)
+                || self.properties.contains_key(key)
+                || fields.contains_key(key) => {}
+            _ => {
+                if let Some(kind) = infer_kind(value) {
+                    fields.insert(key.to_string(), FieldMapping::new(kind));
+                }
+            }
+        }
+    }
+}
+
+pub fn keyword_field(name: &str) -> String {
+    format!("_keyword_{}", name)
+}
+
+pub fn words_field(name: &str) -> String {
+    format!("_words_{}", name)
+}
+
+fn is_valid_field_name(name: &str) -> bool {
+    !name.is_empty() && name != "id" && name != "content" && !name.starts_with('-')
+}
+
+fn words_options() -> TextOptions {
+    TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer("default")
+            .set_index_option(IndexRecordOption::Basic),
+    )
+}
+
+fn text_options(analyzer: &str, record: IndexRecordOption) -> TextOptions {
+    TextOptions::default().set_stored().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(analyzer)
+            .set_index_option(record),
+    )
+}
+
+pub fn infer_kind(value: &Value) -> Option<FieldKind> {
+    match value {
+        Value::String(text) if parse_date(text).is_some() => Some(FieldKind::Date),
+        Value::String(_) => Some(FieldKind::Text),
+        Value::Number(number) if number.is_i64() => Some(FieldKind::Long),
+        Value::Number(_) => Some(FieldKind::Double),
+        Value::Bool(_) => Some(FieldKind::Boolean),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+    }
+}
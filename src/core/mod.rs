@@ -1,3 +1,17 @@
+pub mod aggregation;
+pub mod analysis;
+pub mod bulk;
+pub mod date_math;
 pub mod document;
+pub mod dsl;
+pub mod fuzzy;
+pub mod highlight;
 pub mod index;
+pub mod language;
+pub mod mapping;
+pub mod query;
 pub mod search;
+pub mod sort;
+pub mod spelling;
+pub mod suggest;
+pub mod value;
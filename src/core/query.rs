@@ -0,0 +1,150 @@
+use super::aggregation::{AggregationResult, Aggregations};
+use super::document::Document;
+use super::dsl::QueryDsl;
+use super::highlight::HighlightOptions;
+use super::sort::SortField;
+use super::spelling::Correction;
+use crate::common::error::EngineError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+pub const DEFAULT_SIZE: usize = 10;
+pub const MAX_RESULT_WINDOW: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchAfter(pub Vec<Value>);
+
+impl FromStr for SearchAfter {
+    type Err = EngineError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.trim_start().starts_with('[') {
+            return serde_json::from_str(value)
+                .map(SearchAfter)
+                .map_err(|e| EngineError::InvalidQuery(format!("invalid search_after: {}", e)));
+        }
+
+        Ok(SearchAfter(
+            value
+                .split(',')
+                .map(str::trim)
+                .map(|part| {
+                    serde_json::from_str(part).unwrap_or_else(|_| Value::String(part.to_string()))
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub from: usize,
+    pub size: usize,
+    pub search_after: Option<SearchAfter>,
+    pub min_score: Option<f32>,
+    pub highlight: Option<HighlightOptions>,
+    pub sort: Vec<SortField>,
+    #[serde(alias = "aggregations")]
+    pub aggs: Aggregations,
+    pub fuzzy_prefix_length: usize,
+    pub suggest: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            from: 0,
+            size: DEFAULT_SIZE,
+            search_after: None,
+            min_score: None,
+            highlight: None,
+            sort: Vec::new(),
+            aggs: Aggregations::new(),
+            fuzzy_prefix_length: 0,
+            suggest: false,
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn validate(&self) -> Result<(), EngineError> {
+        if self.search_after.is_some() && self.from > 0 {
+            return Err(EngineError::InvalidQuery(
+                "from must be 0 when search_after is set".to_string(),
+            ));
+        }
+        if self.min_score.is_some_and(|score| !score.is_finite()) {
+            return Err(EngineError::InvalidQuery(
+                "min_score must be a finite number".to_string(),
+            ));
+        }
+        self.window()?;
+        Ok(())
+    }
+
+    pub fn window(&self) -> Result<usize, EngineError> {
+        self.from
+            .checked_add(self.size)
+            .filter(|window| *window <= MAX_RESULT_WINDOW)
+            .ok_or_else(|| {
+                EngineError::InvalidQuery(format!(
+                    "from + size must not exceed {}, use search_after for deep paging",
+                    MAX_RESULT_WINDOW
+                ))
+            })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SearchRequest {
+    #[serde(default)]
+    pub query: QueryDsl,
+    #[serde(flatten)]
+    pub options: SearchOptions,
+}
+
+#[derive(Clone, Debug)]
+pub struct IndexHit {
+    pub id: String,
+    pub score: f32,
+    pub sort: Vec<Value>,
+    pub highlight: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IndexPage {
+    pub total: usize,
+    pub max_score: Option<f32>,
+    pub hits: Vec<IndexHit>,
+    pub aggregations: BTreeMap<String, AggregationResult>,
+    pub suggestions: Vec<Correction>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub document: Document,
+    #[serde(rename = "_score")]
+    pub score: f32,
+    #[serde(rename = "_rank")]
+    pub rank: usize,
+    pub sort: Vec<Value>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub highlight: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SearchResults {
+    pub total: usize,
+    pub max_score: Option<f32>,
+    pub hits: Vec<SearchHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_after: Option<SearchAfter>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub aggregations: BTreeMap<String, AggregationResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<Correction>,
+}
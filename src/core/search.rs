@@ -1,57 +1,395 @@
-use super::document::Document;
+use super::bulk::{BulkAction, BulkItemResponse, BulkItemStatus, BulkOperation};
+use super::document::{Document, Refresh, WriteOptions, WriteResponse, WriteResult};
+use super::dsl::QueryDsl;
 use super::index::SearchIndex;
+use super::mapping::{Mapping, MappingUpdate};
+use super::query::{IndexPage, SearchAfter, SearchHit, SearchOptions, SearchResults};
+use super::suggest::{SuggestRequest, Suggestion};
 use crate::common::config::Config;
+use crate::common::error::EngineError;
 use crate::storage::persistence;
 use anyhow::Result;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 #[derive(Clone)]
 pub struct SearchEngine {
     documents: Arc<RwLock<HashMap<String, Document>>>,
+    tombstones: Arc<std::sync::RwLock<HashMap<String, u64>>>,
     search_index: Arc<SearchIndex>,
+    seq_no: Arc<AtomicU64>,
+    refresh_interval: Option<Duration>,
     config: Config,
 }
 
 impl SearchEngine {
     pub fn new(config: &Config) -> Result<Self> {
-        let documents = match persistence::load_documents(&config.storage.data_file) {
-            Ok(docs) => docs,
-            Err(e) => {
-                tracing::error!("Ошибка загрузки документов: {}", e);
-                HashMap::new()
-            }
-        };
+        let documents = persistence::load_documents(&config.storage.data_file).map_err(|e| {
+            tracing::error!("Ошибка загрузки документов: {}", e);
+            e
+        })?;
+
+        let tombstones =
+            persistence::load_tombstones(&persistence::tombstones_path(&config.storage.data_file))?;
+
+        let refresh_interval = config.indexing.refresh_interval()?;
+        let search_index = Arc::new(SearchIndex::new(
+            &config.storage.index_path,
+            &config.indexing,
+        )?);
+        let remapped = search_index.ensure_mapped(documents.values(), documents.values())?;
+        if !remapped {
+            search_index.reconcile(documents.values())?;
+        }
 
-        let search_index = SearchIndex::new(&config.storage.index_path)?;
+        if let Some(interval) = refresh_interval {
+            spawn_refresh_task(&search_index, interval);
+        }
+
+        let seq_no = documents
+            .values()
+            .map(|doc| doc.seq_no)
+            .fold(tombstones.seq_no, u64::max);
 
         Ok(SearchEngine {
             documents: Arc::new(RwLock::new(documents)),
-            search_index: Arc::new(search_index),
+            tombstones: Arc::new(std::sync::RwLock::new(tombstones.versions)),
+            search_index,
+            seq_no: Arc::new(AtomicU64::new(seq_no)),
+            refresh_interval,
             config: config.clone(),
         })
     }
 
-    pub async fn add_document(&self, doc: Document) -> Result<()> {
-        self.search_index.add_document(&doc).await?;
+    pub async fn add_document(&self, doc: Document) -> Result<WriteResponse> {
+        self.add_document_with_options(doc, &WriteOptions::immediate())
+            .await
+    }
 
+    pub async fn add_document_with_options(
+        &self,
+        doc: Document,
+        options: &WriteOptions,
+    ) -> Result<WriteResponse> {
         let mut docs = self.documents.write().await;
+        let (doc, response) = self.prepare_index(&docs, doc, options.if_version)?;
+
+        self.search_index
+            .ensure_mapped_in_background(std::iter::once(&doc), docs.values())
+            .await?;
+        self.search_index.add_document(&doc).await?;
         docs.insert(doc.id.clone(), doc);
         persistence::save_documents(docs.deref(), &self.config.storage.data_file).await?;
+        drop(docs);
 
-        Ok(())
+        self.apply_refresh(options.refresh).await?;
+        Ok(response)
+    }
+
+    pub async fn get_document(&self, id: &str) -> Option<Document> {
+        self.documents.read().await.get(id).cloned()
+    }
+
+    pub async fn contains_document(&self, id: &str) -> bool {
+        self.documents.read().await.contains_key(id)
+    }
+
+    pub async fn update_document(&self, id: &str, patch: &Value) -> Result<Document> {
+        self.update_document_with_options(id, patch, &WriteOptions::immediate())
+            .await
+    }
+
+    pub async fn update_document_with_options(
+        &self,
+        id: &str,
+        patch: &Value,
+        options: &WriteOptions,
+    ) -> Result<Document> {
+        let mut docs = self.documents.write().await;
+        let merged = docs
+            .get(id)
+            .ok_or_else(|| EngineError::DocumentNotFound(id.to_string()))?
+            .merge_patch(patch)?;
+        let (updated, _) = self.prepare_index(&docs, merged, options.if_version)?;
+
+        self.search_index
+            .ensure_mapped_in_background(std::iter::once(&updated), docs.values())
+            .await?;
+        self.search_index.add_document(&updated).await?;
+        docs.insert(updated.id.clone(), updated.clone());
+        persistence::save_documents(docs.deref(), &self.config.storage.data_file).await?;
+        drop(docs);
+
+        self.apply_refresh(options.refresh).await?;
+        Ok(updated)
+    }
+
+    pub async fn delete_document(&self, id: &str) -> Result<WriteResponse> {
+        self.delete_document_with_options(id, &WriteOptions::immediate())
+            .await
+    }
+
+    pub async fn delete_document_with_options(
+        &self,
+        id: &str,
+        options: &WriteOptions,
+    ) -> Result<WriteResponse> {
+        let mut docs = self.documents.write().await;
+        let response = self.prepare_delete(&docs, id, options.if_version)?;
+
+        self.search_index.delete_document(id).await?;
+        docs.remove(id);
+        self.bury(id, response.version);
+        persistence::save_documents(docs.deref(), &self.config.storage.data_file).await?;
+        self.save_tombstones()?;
+        drop(docs);
+
+        self.apply_refresh(options.refresh).await?;
+        Ok(response)
+    }
+
+    pub async fn bulk(&self, operations: Vec<BulkOperation>) -> Result<Vec<BulkItemResponse>> {
+        self.bulk_with_options(operations, &WriteOptions::immediate())
+            .await
+    }
+
+    pub async fn bulk_with_options(
+        &self,
+        operations: Vec<BulkOperation>,
+        options: &WriteOptions,
+    ) -> Result<Vec<BulkItemResponse>> {
+        let mut docs = self.documents.write().await;
+        let tombstones = self.tombstones.read().unwrap().clone();
+        let seq_no = self.seq_no.load(Ordering::SeqCst);
+
+        let mut items = Vec::with_capacity(operations.len());
+        let mut applied = Vec::new();
+        let mut undo = Vec::new();
+
+        for operation in operations {
+            let item = match operation {
+                BulkOperation::Index { doc, if_version } => {
+                    let id = doc.id.clone();
+                    match self.prepare_index(&docs, doc, if_version) {
+                        Ok((doc, response)) => {
+                            let status = match response.result {
+                                WriteResult::Created => 201,
+                                _ => 200,
+                            };
+                            undo.push((id.clone(), docs.insert(id, doc.clone())));
+                            applied.push(BulkOperation::Index { doc, if_version });
+                            BulkItemStatus::success(status, response)
+                        }
+                        Err(e) => BulkItemStatus::failure(Some(id), e.status_code(), e.to_string()),
+                    }
+                    .with_action(BulkAction::Index)
+                }
+                BulkOperation::Delete { id, if_version } => {
+                    match self.prepare_delete(&docs, &id, if_version) {
+                        Ok(response) => {
+                            undo.push((id.clone(), docs.remove(&id)));
+                            self.bury(&id, response.version);
+                            applied.push(BulkOperation::Delete { id, if_version });
+                            BulkItemStatus::success(200, response)
+                        }
+                        Err(e @ EngineError::DocumentNotFound(_)) => BulkItemStatus {
+                            result: Some(WriteResult::NotFound),
+                            ..BulkItemStatus::failure(Some(id), 404, e.to_string())
+                        },
+                        Err(e) => BulkItemStatus::failure(Some(id), e.status_code(), e.to_string()),
+                    }
+                    .with_action(BulkAction::Delete)
+                }
+                BulkOperation::Invalid { action, id, error } => {
+                    BulkItemStatus::failure(id, 400, error).with_action(action)
+                }
+            };
+            items.push(item);
+        }
+
+        let incoming = applied.iter().filter_map(|operation| match operation {
+            BulkOperation::Index { doc, .. } => Some(doc),
+            _ => None,
+        });
+        let indexed = match self
+            .search_index
+            .ensure_mapped_in_background(incoming, docs.values())
+            .await
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => self.search_index.apply_bulk(&applied).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = indexed {
+            for (id, previous) in undo.into_iter().rev() {
+                match previous {
+                    Some(doc) => docs.insert(id, doc),
+                    None => docs.remove(&id),
+                };
+            }
+            *self.tombstones.write().unwrap() = tombstones;
+            self.seq_no.store(seq_no, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        if !applied.is_empty() {
+            persistence::save_documents(docs.deref(), &self.config.storage.data_file).await?;
+        }
+        if applied
+            .iter()
+            .any(|operation| matches!(operation, BulkOperation::Delete { .. }))
+        {
+            self.save_tombstones()?;
+        }
+        drop(docs);
+
+        self.apply_refresh(options.refresh).await?;
+        Ok(items)
+    }
+
+    pub async fn refresh(&self) -> Result<()> {
+        self.search_index.refresh().await
     }
 
-    pub async fn search(&self, query: &str) -> Result<Vec<Document>> {
-        let ids = self.search_index.search(query)?;
+    async fn apply_refresh(&self, refresh: Refresh) -> Result<()> {
+        match (refresh, self.refresh_interval) {
+            (Refresh::False, _) => Ok(()),
+            (Refresh::WaitFor, Some(_)) => {
+                self.search_index.wait_for_refresh().await;
+                Ok(())
+            }
+            (Refresh::True, _) | (Refresh::WaitFor, None) => self.search_index.refresh().await,
+        }
+    }
+
+    fn prepare_index(
+        &self,
+        docs: &HashMap<String, Document>,
+        mut doc: Document,
+        if_version: Option<u64>,
+    ) -> Result<(Document, WriteResponse), EngineError> {
+        let current = docs.get(&doc.id).map(|existing| existing.version);
+        check_version(&doc.id, current, if_version)?;
+        self.search_index.validate(&doc)?;
+
+        let previous = current.or_else(|| self.tombstones.read().unwrap().get(&doc.id).copied());
+        doc.version = previous.unwrap_or(0) + 1;
+        doc.seq_no = self.next_seq_no();
+
+        let response = WriteResponse {
+            id: doc.id.clone(),
+            result: match current {
+                Some(_) => WriteResult::Updated,
+                None => WriteResult::Created,
+            },
+            version: doc.version,
+            seq_no: doc.seq_no,
+        };
+
+        Ok((doc, response))
+    }
+
+    fn prepare_delete(
+        &self,
+        docs: &HashMap<String, Document>,
+        id: &str,
+        if_version: Option<u64>,
+    ) -> Result<WriteResponse, EngineError> {
+        let current = docs
+            .get(id)
+            .map(|existing| existing.version)
+            .ok_or_else(|| EngineError::DocumentNotFound(id.to_string()))?;
+        check_version(id, Some(current), if_version)?;
+
+        Ok(WriteResponse {
+            id: id.to_string(),
+            result: WriteResult::Deleted,
+            version: current + 1,
+            seq_no: self.next_seq_no(),
+        })
+    }
+
+    fn bury(&self, id: &str, version: u64) {
+        self.tombstones
+            .write()
+            .unwrap()
+            .insert(id.to_string(), version);
+    }
+
+    fn save_tombstones(&self) -> Result<()> {
+        let tombstones = persistence::Tombstones {
+            seq_no: self.seq_no.load(Ordering::SeqCst),
+            versions: self.tombstones.read().unwrap().clone(),
+        };
+        persistence::save_tombstones(
+            &tombstones,
+            &persistence::tombstones_path(&self.config.storage.data_file),
+        )
+    }
+
+    fn next_seq_no(&self) -> u64 {
+        self.seq_no.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub async fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        Ok(self
+            .search_with_options(query, &SearchOptions::default())
+            .await?
+            .hits)
+    }
+
+    pub async fn search_with_options(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<SearchResults> {
+        let page = self.search_index.search(query, options)?;
+        Ok(self.resolve(page, options).await)
+    }
+
+    pub async fn suggest(&self, request: &SuggestRequest) -> Result<Vec<Suggestion>> {
+        self.search_index.suggest(request)
+    }
+
+    pub async fn search_dsl(
+        &self,
+        query: &QueryDsl,
+        options: &SearchOptions,
+    ) -> Result<SearchResults> {
+        let page = self.search_index.search_dsl(query, options)?;
+        Ok(self.resolve(page, options).await)
+    }
+
+    async fn resolve(&self, page: IndexPage, options: &SearchOptions) -> SearchResults {
+        let search_after = page.hits.last().map(|hit| SearchAfter(hit.sort.clone()));
         let docs = self.documents.read().await;
 
-        Ok(ids
-            .into_iter()
-            .filter_map(|id| docs.get(&id).cloned())
-            .collect())
+        SearchResults {
+            total: page.total,
+            max_score: page.max_score,
+            hits: page
+                .hits
+                .into_iter()
+                .filter_map(|hit| docs.get(&hit.id).cloned().map(|document| (document, hit)))
+                .enumerate()
+                .map(|(position, (document, hit))| SearchHit {
+                    document,
+                    score: hit.score,
+                    rank: options.from + position + 1,
+                    sort: hit.sort,
+                    highlight: hit.highlight,
+                })
+                .collect(),
+            search_after,
+            aggregations: page.aggregations,
+            suggestions: page.suggestions,
+        }
     }
 
     pub async fn close(&self) -> Result<()> {
@@ -63,23 +401,87 @@ impl SearchEngine {
         &self,
         query: &str,
         fields: &[&str],
-    ) -> Result<Vec<Document>> {
-        let ids = self.search_index.search_with_metadata(query, fields)?;
-        let docs = self.documents.read().await;
-
-        Ok(ids
-            .into_iter()
-            .filter_map(|id| docs.get(&id).cloned())
-            .collect())
+    ) -> Result<Vec<SearchHit>> {
+        let options = SearchOptions::default();
+        let page = self
+            .search_index
+            .search_with_metadata(query, fields, &options)?;
+        Ok(self.resolve(page, &options).await.hits)
     }
 
     pub async fn add_metadata_field(&self, field_name: &str) -> Result<()> {
-        self.search_index.add_metadata_field(field_name).await?;
+        let docs = self.documents.write().await;
+        self.search_index
+            .add_metadata_field(field_name, docs.values())
+            .await?;
         Ok(())
     }
 
+    pub fn mapping(&self) -> Mapping {
+        self.search_index.mapping()
+    }
+
+    pub async fn put_mapping(&self, update: MappingUpdate) -> Result<Mapping> {
+        let docs = self.documents.write().await;
+        let current = self.search_index.mapping();
+        let mapping = current.merge(update)?;
+        if mapping == current {
+            return Ok(current);
+        }
+
+        for doc in docs.values() {
+            mapping.validate_document(doc).map_err(|e| {
+                EngineError::InvalidMapping(format!(
+                    "existing document '{}' does not match: {}",
+                    doc.id, e
+                ))
+            })?;
+        }
+
+        self.search_index
+            .rebuild_in_background(mapping, docs.values().cloned().collect())
+            .await?;
+        Ok(self.search_index.mapping())
+    }
+
     pub async fn update_index_schema(&self) -> Result<()> {
         self.search_index.update_schema().await?;
         Ok(())
     }
 }
+
+fn check_version(id: &str, current: Option<u64>, expected: Option<u64>) -> Result<(), EngineError> {
+    match expected {
+        Some(expected) if current.unwrap_or(0) != expected => Err(EngineError::VersionConflict {
+            id: id.to_string(),
+            expected,
+            current: current.unwrap_or(0),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn spawn_refresh_task(search_index: &Arc<SearchIndex>, interval: Duration) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        tracing::warn!("No async runtime available, scheduled refresh is disabled");
+        return;
+    };
+
+    let search_index = Arc::downgrade(search_index);
+    runtime.spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let Some(search_index) = search_index.upgrade() else {
+                break;
+            };
+            if search_index.has_pending() {
+                if let Err(e) = search_index.refresh().await {
+                    tracing::error!("Scheduled refresh failed: {}", e);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,365 @@
+use super::mapping::keyword_field;
+use crate::common::error::EngineError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::str::FromStr;
+use std::sync::Arc;
+use tantivy::fastfield::{Column, FastValue, MultiValuedFastFieldReader};
+use tantivy::schema::{Field, FieldType, Schema};
+use tantivy::{DateTime, DocId, InvertedIndexReader, Score, SegmentReader};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Value")]
+pub struct SortField {
+    pub field: String,
+    pub order: SortOrder,
+}
+
+impl SortField {
+    pub fn new(field: &str, order: SortOrder) -> Self {
+        SortField {
+            field: field.to_string(),
+            order,
+        }
+    }
+}
+
+impl FromStr for SortField {
+    type Err = EngineError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (field, order) = match value.rsplit_once(':') {
+            Some((field, "asc")) => (field, SortOrder::Asc),
+            Some((field, "desc")) => (field, SortOrder::Desc),
+            Some((_, order)) => {
+                return Err(EngineError::InvalidQuery(format!(
+                    "sort order must be 'asc' or 'desc', got '{}'",
+                    order
+                )))
+            }
+            None if value == "_score" => (value, SortOrder::Desc),
+            None => (value, SortOrder::Asc),
+        };
+
+        if field.is_empty() {
+            return Err(EngineError::InvalidQuery(
+                "sort field must not be empty".to_string(),
+            ));
+        }
+        Ok(SortField::new(field, order))
+    }
+}
+
+impl TryFrom<Value> for SortField {
+    type Error = EngineError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(spec) => spec.parse(),
+            Value::Object(object) if object.len() == 1 => {
+                let (field, order) = object.into_iter().next().unwrap();
+                let order = match order {
+                    Value::Object(mut options) => options.remove("order").unwrap_or(Value::Null),
+                    order => order,
+                };
+                match order.as_str() {
+                    Some(order) => format!("{}:{}", field, order).parse(),
+                    None => Err(EngineError::InvalidQuery(format!(
+                        "sort order for '{}' must be 'asc' or 'desc'",
+                        field
+                    ))),
+                }
+            }
+            value => Err(EngineError::InvalidQuery(format!(
+                "invalid sort specification {}",
+                value
+            ))),
+        }
+    }
+}
+
+pub fn parse_sort(spec: &str) -> Result<Vec<SortField>, EngineError> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize)]
+#[serde(untagged)]
+pub enum SortValue {
+    Missing,
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+}
+
+impl SortValue {
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SortKey(Vec<(SortValue, SortOrder)>);
+
+impl SortKey {
+    pub fn values(&self) -> Vec<Value> {
+        self.0.iter().map(|(value, _)| value.to_json()).collect()
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        for ((left, order), (right, _)) in self.0.iter().zip(&other.0) {
+            let ordering = match (left, right) {
+                (SortValue::Missing, SortValue::Missing) => Ordering::Equal,
+                (SortValue::Missing, _) => Ordering::Less,
+                (_, SortValue::Missing) => Ordering::Greater,
+                (left, right) => {
+                    let ordering = left.partial_cmp(right)?;
+                    match order {
+                        SortOrder::Asc => ordering.reverse(),
+                        SortOrder::Desc => ordering,
+                    }
+                }
+            };
+            if ordering != Ordering::Equal {
+                return Some(ordering);
+            }
+        }
+        Some(self.0.len().cmp(&other.0.len()))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SortSource {
+    Score,
+    SeqNo(Field),
+    Bool(Field),
+    I64(Field),
+    U64(Field),
+    F64(Field),
+    Date(Field),
+    Keyword(Field),
+}
+
+#[derive(Clone, Debug)]
+pub struct Sorter {
+    sources: Vec<(SortSource, SortOrder)>,
+}
+
+impl Sorter {
+    pub fn new(schema: &Schema, sort: &[SortField]) -> Result<Self, EngineError> {
+        let mut sources = Vec::with_capacity(sort.len() + 2);
+        for sort_field in sort {
+            sources.push((source(schema, &sort_field.field)?, sort_field.order));
+        }
+
+        if !sort.iter().any(|sort_field| sort_field.field == "_score") {
+            sources.push((SortSource::Score, SortOrder::Desc));
+        }
+        let seq_no = schema.get_field("_seq_no").unwrap();
+        sources.push((SortSource::SeqNo(seq_no), SortOrder::Desc));
+
+        Ok(Sorter { sources })
+    }
+
+    pub fn cursor(&self, values: &[Value]) -> Result<SortKey, EngineError> {
+        if values.len() != self.sources.len() {
+            return Err(EngineError::InvalidQuery(format!(
+                "search_after expects {} values, got {}",
+                self.sources.len(),
+                values.len()
+            )));
+        }
+
+        let key = self
+            .sources
+            .iter()
+            .zip(values)
+            .map(|((source, order), value)| {
+                cursor_value(*source, value)
+                    .map(|value| (value, *order))
+                    .ok_or_else(|| {
+                        EngineError::InvalidQuery(format!("invalid search_after value {}", value))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SortKey(key))
+    }
+
+    pub fn for_segment(&self, segment_reader: &SegmentReader) -> tantivy::Result<SegmentSorter> {
+        let columns = self
+            .sources
+            .iter()
+            .map(|(source, order)| {
+                SegmentColumn::open(*source, segment_reader).map(|column| (column, *order))
+            })
+            .collect::<tantivy::Result<_>>()?;
+        Ok(SegmentSorter { columns })
+    }
+}
+
+fn source(schema: &Schema, name: &str) -> Result<SortSource, EngineError> {
+    if name == "_score" {
+        return Ok(SortSource::Score);
+    }
+
+    let not_sortable = || {
+        EngineError::InvalidQuery(format!(
+            "field '{}' is not sortable, sort on a fast keyword, numeric, boolean or date field",
+            name
+        ))
+    };
+    if name.starts_with('_') {
+        return Err(not_sortable());
+    }
+    let field = schema
+        .get_field(&keyword_field(name))
+        .or_else(|| schema.get_field(name))
+        .ok_or_else(not_sortable)?;
+    let entry = schema.get_field_entry(field);
+    if !entry.is_fast() {
+        return Err(not_sortable());
+    }
+
+    match entry.field_type() {
+        FieldType::Bool(_) => Ok(SortSource::Bool(field)),
+        FieldType::I64(_) => Ok(SortSource::I64(field)),
+        FieldType::U64(_) => Ok(SortSource::U64(field)),
+        FieldType::F64(_) => Ok(SortSource::F64(field)),
+        FieldType::Date(_) => Ok(SortSource::Date(field)),
+        FieldType::Str(_) => Ok(SortSource::Keyword(field)),
+        _ => Err(not_sortable()),
+    }
+}
+
+fn cursor_value(source: SortSource, value: &Value) -> Option<SortValue> {
+    if value.is_null() {
+        return Some(SortValue::Missing);
+    }
+
+    match source {
+        SortSource::Score | SortSource::F64(_) => value.as_f64().map(SortValue::F64),
+        SortSource::SeqNo(_) | SortSource::U64(_) => value.as_u64().map(SortValue::U64),
+        SortSource::I64(_) | SortSource::Date(_) => value.as_i64().map(SortValue::I64),
+        SortSource::Bool(_) => value.as_bool().map(SortValue::Bool),
+        SortSource::Keyword(_) => value.as_str().map(|text| SortValue::Str(text.to_string())),
+    }
+}
+
+enum SegmentColumn {
+    Score,
+    SeqNo(Arc<dyn Column<u64>>),
+    Bool(MultiValuedFastFieldReader<bool>),
+    I64(MultiValuedFastFieldReader<i64>),
+    U64(MultiValuedFastFieldReader<u64>),
+    F64(MultiValuedFastFieldReader<f64>),
+    Date(MultiValuedFastFieldReader<DateTime>),
+    Keyword(MultiValuedFastFieldReader<u64>, Arc<InvertedIndexReader>),
+}
+
+impl SegmentColumn {
+    fn open(source: SortSource, segment_reader: &SegmentReader) -> tantivy::Result<Self> {
+        let fast_fields = segment_reader.fast_fields();
+        Ok(match source {
+            SortSource::Score => SegmentColumn::Score,
+            SortSource::SeqNo(field) => SegmentColumn::SeqNo(fast_fields.u64(field)?),
+            SortSource::Bool(field) => SegmentColumn::Bool(fast_fields.bools(field)?),
+            SortSource::I64(field) => SegmentColumn::I64(fast_fields.i64s(field)?),
+            SortSource::U64(field) => SegmentColumn::U64(fast_fields.u64s(field)?),
+            SortSource::F64(field) => SegmentColumn::F64(fast_fields.f64s(field)?),
+            SortSource::Date(field) => SegmentColumn::Date(fast_fields.dates(field)?),
+            SortSource::Keyword(field) => SegmentColumn::Keyword(
+                fast_fields.u64s(field)?,
+                segment_reader.inverted_index(field)?,
+            ),
+        })
+    }
+
+    fn value(&self, doc: DocId, score: Score, order: SortOrder) -> SortValue {
+        match self {
+            SegmentColumn::Score => SortValue::F64(score as f64),
+            SegmentColumn::SeqNo(column) => SortValue::U64(column.get_val(doc)),
+            SegmentColumn::Bool(reader) => pick(reader, doc, order)
+                .map(SortValue::Bool)
+                .unwrap_or(SortValue::Missing),
+            SegmentColumn::I64(reader) => pick(reader, doc, order)
+                .map(SortValue::I64)
+                .unwrap_or(SortValue::Missing),
+            SegmentColumn::U64(reader) => pick(reader, doc, order)
+                .map(SortValue::U64)
+                .unwrap_or(SortValue::Missing),
+            SegmentColumn::F64(reader) => pick(reader, doc, order)
+                .map(SortValue::F64)
+                .unwrap_or(SortValue::Missing),
+            SegmentColumn::Date(reader) => pick(reader, doc, order)
+                .map(|date| SortValue::I64(date.into_timestamp_millis()))
+                .unwrap_or(SortValue::Missing),
+            SegmentColumn::Keyword(reader, inverted_index) => {
+                let mut bytes = Vec::new();
+                pick(reader, doc, order)
+                    .filter(|ord| {
+                        inverted_index
+                            .terms()
+                            .ord_to_term(*ord, &mut bytes)
+                            .unwrap_or(false)
+                    })
+                    .map(|_| SortValue::Str(String::from_utf8_lossy(&bytes).into_owned()))
+                    .unwrap_or(SortValue::Missing)
+            }
+        }
+    }
+}
+
+fn pick<T: FastValue>(
+    reader: &MultiValuedFastFieldReader<T>,
+    doc: DocId,
+    order: SortOrder,
+) -> Option<T> {
+    let mut values = Vec::new();
+    reader.get_vals(doc, &mut values);
+    values.into_iter().reduce(|best, value| {
+        let better = match order {
+            SortOrder::Asc => value < best,
+            SortOrder::Desc => value > best,
+        };
+        if better {
+            value
+        } else {
+            best
+        }
+    })
+}
+
+pub struct SegmentSorter {
+    columns: Vec<(SegmentColumn, SortOrder)>,
+}
+
+impl SegmentSorter {
+    pub fn key(&self, doc: DocId, score: Score) -> SortKey {
+        SortKey(
+            self.columns
+                .iter()
+                .map(|(column, order)| (column.value(doc, score, *order), *order))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,152 @@
+use super::fuzzy::{edit_distance, Fuzziness};
+use super::mapping::words_field;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use tantivy::schema::FieldType;
+use tantivy::{Index, Searcher, Term};
+
+pub const MAX_CORRECTIONS: usize = 3;
+const CANDIDATES_PER_WORD: usize = 3;
+const MAX_COMBINATIONS: usize = 27;
+const MAX_SCANNED_TERMS: usize = 10_000;
+const OPERATORS: &[&str] = &["AND", "OR", "NOT"];
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Correction {
+    pub text: String,
+    pub hits: u64,
+}
+
+pub(crate) fn corrections(
+    index: &Index,
+    searcher: &Searcher,
+    query: &str,
+    mut count: impl FnMut(&str) -> Result<u64>,
+) -> Result<Vec<Correction>> {
+    let mut alternatives = Vec::new();
+    let mut misspelled = false;
+    for word in query.split_whitespace() {
+        let candidates = candidates(index, searcher, word)?;
+        if candidates.is_empty() {
+            alternatives.push(vec![word.to_string()]);
+        } else {
+            misspelled = true;
+            alternatives.push(candidates);
+        }
+    }
+    if !misspelled {
+        return Ok(Vec::new());
+    }
+
+    let mut queries = vec![Vec::<String>::new()];
+    for options in &alternatives {
+        queries = queries
+            .into_iter()
+            .flat_map(|words| {
+                options.iter().map(move |option| {
+                    let mut words = words.clone();
+                    words.push(option.clone());
+                    words
+                })
+            })
+            .take(MAX_COMBINATIONS)
+            .collect();
+    }
+
+    let mut corrections = Vec::new();
+    for words in queries {
+        let text = words.join(" ");
+        let hits = count(&text)?;
+        if hits > 0 {
+            corrections.push(Correction { text, hits });
+        }
+    }
+    corrections.sort_by_key(|correction| Reverse(correction.hits));
+    corrections.truncate(MAX_CORRECTIONS);
+    Ok(corrections)
+}
+
+fn candidates(index: &Index, searcher: &Searcher, word: &str) -> Result<Vec<String>> {
+    let start = word.len() - word.trim_start_matches(['+', '-', '(']).len();
+    let body = word[start..].trim_end_matches(')');
+    let (prefix, suffix) = (&word[..start], &word[start + body.len()..]);
+    if OPERATORS.contains(&body) {
+        return Ok(Vec::new());
+    }
+
+    let schema = index.schema();
+    let (name, value) = body.split_once(':').unwrap_or(("content", body));
+    let field_prefix = match body.contains(':') {
+        true => format!("{}:", name),
+        false => String::new(),
+    };
+    let Some(field) = schema.get_field(name) else {
+        return Ok(Vec::new());
+    };
+    if value.is_empty()
+        || !value.chars().all(char::is_alphanumeric)
+        || !matches!(
+            schema.get_field_entry(field).field_type(),
+            FieldType::Str(_)
+        )
+    {
+        return Ok(Vec::new());
+    }
+    let field = schema.get_field(&words_field(name)).unwrap_or(field);
+
+    let mut stream = index.tokenizer_for_field(field)?.token_stream(value);
+    let mut tokens = Vec::new();
+    while stream.advance() {
+        tokens.push(stream.token().text.clone());
+    }
+    let [token] = tokens.as_slice() else {
+        return Ok(Vec::new());
+    };
+    if searcher.doc_freq(&Term::from_field_text(field, token))? > 0 {
+        return Ok(Vec::new());
+    }
+    let max = Fuzziness::Auto.distance(token) as usize;
+    let Some(first) = token.chars().next().filter(|_| max > 0) else {
+        return Ok(Vec::new());
+    };
+
+    let target: Vec<char> = token.chars().collect();
+    let first = first.to_string();
+    let mut terms = BTreeSet::new();
+    for segment in searcher.segment_readers() {
+        let inverted_index = segment.inverted_index(field)?;
+        let mut stream = inverted_index
+            .terms()
+            .range()
+            .ge(first.as_bytes())
+            .into_stream()?;
+        let mut scanned = 0;
+        while stream.advance() && scanned < MAX_SCANNED_TERMS {
+            if !stream.key().starts_with(first.as_bytes()) {
+                break;
+            }
+            scanned += 1;
+            let Ok(candidate) = std::str::from_utf8(stream.key()) else {
+                continue;
+            };
+            if let Some(distance) = edit_distance(&target, candidate, max) {
+                terms.insert((distance, candidate.to_string()));
+            }
+        }
+    }
+
+    let mut scored = Vec::new();
+    for (distance, candidate) in terms {
+        let doc_freq = searcher.doc_freq(&Term::from_field_text(field, &candidate))?;
+        scored.push((distance, Reverse(doc_freq), candidate));
+    }
+    scored.sort();
+
+    Ok(scored
+        .into_iter()
+        .take(CANDIDATES_PER_WORD)
+        .map(|(_, _, candidate)| format!("{}{}{}{}", prefix, field_prefix, candidate, suffix))
+        .collect())
+}
@@ -0,0 +1,253 @@
+use super::dsl::{FieldQuery, MatchQuery, Operator, QueryDsl};
+use super::mapping::words_field;
+use crate::common::error::EngineError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tantivy::query::{BooleanQuery, EnableScoring, Occur, Query, TermQuery};
+use tantivy::schema::{Field, FieldType, IndexRecordOption};
+use tantivy::{DocSet, Index, Searcher, SegmentReader, Term, TERMINATED};
+
+pub const DEFAULT_SUGGESTIONS: usize = 5;
+pub const MAX_SUGGESTIONS: usize = 100;
+const MAX_CANDIDATES: usize = 1_000;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SuggestRequest {
+    pub prefix: String,
+    pub size: usize,
+    pub fields: Vec<String>,
+    pub category: Option<String>,
+}
+
+impl Default for SuggestRequest {
+    fn default() -> Self {
+        SuggestRequest {
+            prefix: String::new(),
+            size: DEFAULT_SUGGESTIONS,
+            fields: vec!["content".to_string()],
+            category: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub text: String,
+    pub field: String,
+    pub doc_count: u64,
+}
+
+pub fn suggest(
+    index: &Index,
+    searcher: &Searcher,
+    request: &SuggestRequest,
+) -> Result<Vec<Suggestion>> {
+    if request.prefix.trim().is_empty() {
+        return Err(EngineError::InvalidQuery("prefix must not be empty".to_string()).into());
+    }
+    if request.size == 0 || request.size > MAX_SUGGESTIONS {
+        return Err(EngineError::InvalidQuery(format!(
+            "size must be between 1 and {}",
+            MAX_SUGGESTIONS
+        ))
+        .into());
+    }
+
+    let schema = index.schema();
+    let category = match &request.category {
+        Some(category) => Some(
+            QueryDsl::Match(FieldQuery {
+                field: "category".to_string(),
+                value: MatchQuery {
+                    query: Value::String(category.clone()),
+                    operator: Operator::And,
+                    fuzziness: None,
+                    prefix_length: 0,
+                    slop: 0,
+                },
+            })
+            .compile(index)?,
+        ),
+        None => None,
+    };
+
+    let mut suggestions: BTreeMap<String, Suggestion> = BTreeMap::new();
+    for name in &request.fields {
+        let field = schema
+            .get_field(name)
+            .filter(|field| match schema.get_field_entry(*field).field_type() {
+                FieldType::Str(options) => options.get_indexing_options().is_some(),
+                _ => false,
+            })
+            .ok_or_else(|| {
+                EngineError::InvalidQuery(format!(
+                    "field '{}' cannot be used for suggestions, suggest on a text or keyword field",
+                    name
+                ))
+            })?;
+
+        let field = schema.get_field(&words_field(name)).unwrap_or(field);
+        let completion = Completion::new(index, field, &request.prefix)?;
+        let Some(partial) = &completion.partial else {
+            continue;
+        };
+
+        let mut filters: Vec<(Occur, Box<dyn Query>)> = completion
+            .context_terms
+            .iter()
+            .map(|term| -> (Occur, Box<dyn Query>) {
+                (
+                    Occur::Must,
+                    Box::new(TermQuery::new(term.clone(), IndexRecordOption::Basic)),
+                )
+            })
+            .collect();
+        if let Some(category) = &category {
+            filters.push((Occur::Must, category.box_clone()));
+        }
+        let filter = match filters.is_empty() {
+            true => None,
+            false => Some(BooleanQuery::new(filters).weight(EnableScoring::Disabled(&schema))?),
+        };
+
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for segment in searcher.segment_readers() {
+            let allowed = match &filter {
+                Some(weight) => {
+                    let mut scorer = weight.scorer(segment, 1.0)?;
+                    let mut allowed = vec![false; segment.max_doc() as usize];
+                    while scorer.doc() != TERMINATED {
+                        allowed[scorer.doc() as usize] = true;
+                        scorer.advance();
+                    }
+                    Some(allowed)
+                }
+                None => None,
+            };
+            count_completions(segment, field, partial, allowed.as_deref(), &mut counts)?;
+        }
+
+        for (term, doc_count) in counts {
+            let text = match completion.context.is_empty() {
+                true => term,
+                false => format!("{} {}", completion.context, term),
+            };
+            let suggestion = Suggestion {
+                text: text.clone(),
+                field: name.clone(),
+                doc_count,
+            };
+            match suggestions.get(&text) {
+                Some(existing) if existing.doc_count >= doc_count => {}
+                _ => {
+                    suggestions.insert(text, suggestion);
+                }
+            }
+        }
+    }
+
+    let mut suggestions: Vec<Suggestion> = suggestions.into_values().collect();
+    suggestions.sort_by(|a, b| b.doc_count.cmp(&a.doc_count).then(a.text.cmp(&b.text)));
+    suggestions.truncate(request.size);
+    Ok(suggestions)
+}
+
+struct Completion {
+    context: String,
+    context_terms: Vec<Term>,
+    partial: Option<String>,
+}
+
+impl Completion {
+    fn new(index: &Index, field: Field, prefix: &str) -> Result<Self, EngineError> {
+        let analyzer = index
+            .tokenizer_for_field(field)
+            .map_err(|e| EngineError::InvalidQuery(e.to_string()))?;
+        let is_raw = matches!(
+            index.schema().get_field_entry(field).field_type(),
+            FieldType::Str(options) if options
+                .get_indexing_options()
+                .is_some_and(|indexing| indexing.tokenizer() == "raw")
+        );
+
+        if is_raw {
+            return Ok(Completion {
+                context: String::new(),
+                context_terms: Vec::new(),
+                partial: Some(prefix.trim_start().to_string()),
+            });
+        }
+
+        let words: Vec<&str> = prefix.split_whitespace().collect();
+        let (partial, context) = words.split_last().unwrap_or((&"", &[]));
+
+        let mut context_terms = Vec::new();
+        for word in context {
+            let mut stream = analyzer.token_stream(word);
+            while stream.advance() {
+                context_terms.push(Term::from_field_text(field, &stream.token().text));
+            }
+        }
+
+        let mut stream = analyzer.token_stream(partial);
+        let mut last = None;
+        while stream.advance() {
+            last = Some(stream.token().text.clone());
+        }
+
+        Ok(Completion {
+            context: context.join(" "),
+            context_terms,
+            partial: last,
+        })
+    }
+}
+
+fn count_completions(
+    segment: &SegmentReader,
+    field: Field,
+    partial: &str,
+    allowed: Option<&[bool]>,
+    counts: &mut BTreeMap<String, u64>,
+) -> tantivy::Result<()> {
+    let inverted_index = segment.inverted_index(field)?;
+    let mut stream = inverted_index
+        .terms()
+        .range()
+        .ge(partial.as_bytes())
+        .into_stream()?;
+
+    let mut candidates = 0;
+    while stream.advance() && candidates < MAX_CANDIDATES {
+        if !stream.key().starts_with(partial.as_bytes()) {
+            break;
+        }
+        let Ok(term) = std::str::from_utf8(stream.key()) else {
+            continue;
+        };
+        candidates += 1;
+
+        let mut postings = inverted_index
+            .read_block_postings_from_terminfo(stream.value(), IndexRecordOption::Basic)?;
+        let mut doc_count = 0;
+        while !postings.docs().is_empty() {
+            doc_count += postings
+                .docs()
+                .iter()
+                .filter(|doc| !segment.is_deleted(**doc))
+                .filter(|doc| match allowed {
+                    Some(allowed) => allowed[**doc as usize],
+                    None => true,
+                })
+                .count() as u64;
+            postings.advance();
+        }
+        if doc_count > 0 {
+            *counts.entry(term.to_string()).or_default() += doc_count;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use serde_json::Value;
+use tantivy::schema::{Facet, Field, FieldType, Value as TantivyValue};
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::time::OffsetDateTime;
+use tantivy::{DateTime, Term};
+
+pub fn parse_date(text: &str) -> Option<DateTime> {
+    OffsetDateTime::parse(text, &Rfc3339)
+        .ok()
+        .map(DateTime::from_utc)
+}
+
+pub fn date_from_millis(millis: i64) -> Option<DateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(millis as i128 * 1_000_000)
+        .ok()
+        .map(DateTime::from_utc)
+}
+
+pub fn to_tantivy_value(field_type: &FieldType, value: &Value) -> Option<TantivyValue> {
+    match (field_type, value) {
+        (FieldType::Str(_), Value::String(text)) => Some(TantivyValue::Str(text.clone())),
+        (FieldType::Str(_), Value::Number(number)) => Some(TantivyValue::Str(number.to_string())),
+        (FieldType::Str(_), Value::Bool(flag)) => Some(TantivyValue::Str(flag.to_string())),
+        (FieldType::I64(_), Value::Number(number)) => number.as_i64().map(TantivyValue::I64),
+        (FieldType::I64(_), Value::String(text)) => text.parse().ok().map(TantivyValue::I64),
+        (FieldType::U64(_), Value::Number(number)) => number.as_u64().map(TantivyValue::U64),
+        (FieldType::U64(_), Value::String(text)) => text.parse().ok().map(TantivyValue::U64),
+        (FieldType::F64(_), Value::Number(number)) => number.as_f64().map(TantivyValue::F64),
+        (FieldType::F64(_), Value::String(text)) => text.parse().ok().map(TantivyValue::F64),
+        (FieldType::Bool(_), Value::Bool(flag)) => Some(TantivyValue::Bool(*flag)),
+        (FieldType::Bool(_), Value::String(text)) => text.parse().ok().map(TantivyValue::Bool),
+        (FieldType::Date(_), Value::String(text)) => parse_date(text).map(TantivyValue::Date),
+        (FieldType::Date(_), Value::Number(number)) => number
+            .as_i64()
+            .and_then(date_from_millis)
+            .map(TantivyValue::Date),
+        (FieldType::Facet(_), Value::String(path)) => {
+            Facet::from_text(path).ok().map(TantivyValue::Facet)
+        }
+        _ => None,
+    }
+}
+
+pub fn to_term(field: Field, field_type: &FieldType, value: &Value) -> Option<Term> {
+    match to_tantivy_value(field_type, value)? {
+        TantivyValue::Str(text) => Some(Term::from_field_text(field, &text)),
+        TantivyValue::I64(number) => Some(Term::from_field_i64(field, number)),
+        TantivyValue::U64(number) => Some(Term::from_field_u64(field, number)),
+        TantivyValue::F64(number) => Some(Term::from_field_f64(field, number)),
+        TantivyValue::Bool(flag) => Some(Term::from_field_bool(field, flag)),
+        TantivyValue::Date(date) => Some(Term::from_field_date(field, date)),
+        TantivyValue::Facet(facet) => Some(Term::from_facet(field, &facet)),
+        _ => None,
+    }
+}
@@ -15,7 +15,7 @@ async fn main() -> anyhow::Result<()> {
     info!("Search engine initialized");
 
     let addr = SocketAddr::new(config.server.host, config.server.port);
-    let routes = search_routes(engine);
+    let routes = search_routes(engine.clone());
 
     info!("Starting server on {}", addr);
 
@@ -40,5 +40,7 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    engine.close().await?;
+
     Ok(())
 }
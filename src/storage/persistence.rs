@@ -1,33 +1,116 @@
 use crate::core::document::Document;
+use crate::core::mapping::Mapping;
 use anyhow::Result;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::{self, File, OpenOptions};
+use std::fs::{self, File};
 use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct UnversionedDocument {
+    id: String,
+    content: String,
+    metadata: HashMap<String, String>,
+}
+
+fn decode_legacy(bytes: &[u8]) -> Result<HashMap<String, Document>> {
+    let docs: HashMap<String, UnversionedDocument> = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)?;
+    let mut docs: Vec<(String, UnversionedDocument)> = docs.into_iter().collect();
+    docs.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(docs
+        .into_iter()
+        .zip(1..)
+        .map(|((key, doc), seq_no)| {
+            let doc = Document {
+                id: doc.id,
+                content: doc.content,
+                metadata: doc
+                    .metadata
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+                version: 1,
+                seq_no,
+            };
+            (key, doc)
+        })
+        .collect())
+}
 
 pub async fn save_documents(docs: &HashMap<String, Document>, path: &str) -> Result<()> {
     if let Some(parent) = Path::new(path).parent() {
         fs::create_dir_all(parent)?;
     }
 
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(path)?;
-
-    let writer = BufWriter::new(file);
-    bincode::serialize_into(writer, &docs)?;
+    let temp_path = format!("{}.tmp", path);
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    serde_json::to_writer(&mut writer, &docs)?;
+    writer.into_inner()?.sync_all()?;
+    fs::rename(temp_path, path)?;
     Ok(())
 }
 
 pub fn load_documents(path: &str) -> Result<HashMap<String, Document>> {
-    match File::open(path) {
-        Ok(file) => {
-            let reader = BufReader::new(file);
-            Ok(bincode::deserialize_from(reader)?)
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    match serde_json::from_reader(BufReader::new(file)) {
+        Ok(docs) => Ok(docs),
+        Err(json_error) => {
+            let legacy = decode_legacy(&fs::read(path)?).map_err(|_| json_error)?;
+            tracing::info!("Migrating {} documents from bincode storage", legacy.len());
+            Ok(legacy)
         }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+    }
+}
+
+pub fn save_mapping(mapping: &Mapping, path: &Path) -> Result<()> {
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec_pretty(mapping)?)?;
+    fs::rename(temp_path, path)?;
+    Ok(())
+}
+
+pub fn load_mapping(path: &Path) -> Result<Option<Mapping>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn tombstones_path(data_file: &str) -> PathBuf {
+    Path::new(data_file).with_extension("tombstones.json")
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Tombstones {
+    pub seq_no: u64,
+    pub versions: HashMap<String, u64>,
+}
+
+pub fn save_tombstones(tombstones: &Tombstones, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec(tombstones)?)?;
+    fs::rename(temp_path, path)?;
+    Ok(())
+}
+
+pub fn load_tombstones(path: &Path) -> Result<Tombstones> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Tombstones::default()),
         Err(e) => Err(e.into()),
     }
 }
@@ -1,5 +1,10 @@
-use rust_search::api::handlers::{handle_add_document, handle_rejection, handle_search, json_body};
-use rust_search::common::config::Config;
+use rust_search::api::handlers::{
+    bulk_body, handle_add_document, handle_bulk, handle_delete_document, handle_get_document,
+    handle_get_mapping, handle_head_document, handle_put_mapping, handle_rejection, handle_search,
+    handle_search_dsl, handle_suggest, handle_update_document, json_body, mapping_body, patch_body,
+    search_body,
+};
+use rust_search::common::config::{Config, IndexingConfig};
 use rust_search::{Document, SearchEngine};
 use serde_json::json;
 use std::collections::HashMap;
@@ -22,18 +27,20 @@ pub fn create_test_config() -> Config {
             data_file: data_path.to_str().unwrap().to_string(),
             index_path: index_path.to_str().unwrap().to_string(),
         },
+        indexing: IndexingConfig::default(),
     }
 }
 
 pub fn create_test_document(id: &str, content: &str) -> Document {
     let mut metadata = HashMap::new();
-    metadata.insert("author".to_string(), "Test Author".to_string());
-    metadata.insert("type".to_string(), "test".to_string());
+    metadata.insert("author".to_string(), "Test Author".into());
+    metadata.insert("type".to_string(), "test".into());
 
     Document {
         id: id.to_string(),
         content: content.to_string(),
         metadata,
+        ..Default::default()
     }
 }
 
@@ -47,6 +54,7 @@ pub async fn create_test_filter() -> impl warp::Filter<Extract = impl warp::Repl
     let add_document = warp::post()
         .and(warp::path("document"))
         .and(json_body())
+        .and(warp::query::<HashMap<String, String>>())
         .and(search_engine_filter.clone())
         .and_then(handle_add_document);
 
@@ -56,7 +64,71 @@ pub async fn create_test_filter() -> impl warp::Filter<Extract = impl warp::Repl
         .and(search_engine_filter.clone())
         .and_then(handle_search);
 
-    add_document.or(search).recover(handle_rejection)
+    let search_dsl = warp::post()
+        .and(warp::path("search"))
+        .and(search_body())
+        .and(search_engine_filter.clone())
+        .and_then(handle_search_dsl);
+
+    let suggest = warp::get()
+        .and(warp::path("suggest"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(search_engine_filter.clone())
+        .and_then(handle_suggest);
+
+    let delete_document = warp::delete()
+        .and(warp::path!("document" / String))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(search_engine_filter.clone())
+        .and_then(handle_delete_document);
+
+    let bulk = warp::post()
+        .and(warp::path!("document" / "_bulk"))
+        .and(bulk_body())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(search_engine_filter.clone())
+        .and_then(handle_bulk);
+
+    let get_document = warp::get()
+        .and(warp::path!("document" / String))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(search_engine_filter.clone())
+        .and_then(handle_get_document);
+
+    let head_document = warp::head()
+        .and(warp::path!("document" / String))
+        .and(search_engine_filter.clone())
+        .and_then(handle_head_document);
+
+    let update_document = warp::post()
+        .and(warp::path!("document" / String / "_update"))
+        .and(patch_body())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(search_engine_filter.clone())
+        .and_then(handle_update_document);
+
+    let get_mapping = warp::get()
+        .and(warp::path!("_mapping"))
+        .and(search_engine_filter.clone())
+        .and_then(handle_get_mapping);
+
+    let put_mapping = warp::put()
+        .and(warp::path!("_mapping"))
+        .and(mapping_body())
+        .and(search_engine_filter.clone())
+        .and_then(handle_put_mapping);
+
+    bulk.or(update_document)
+        .or(add_document)
+        .or(get_document)
+        .or(head_document)
+        .or(search)
+        .or(search_dsl)
+        .or(suggest)
+        .or(delete_document)
+        .or(get_mapping)
+        .or(put_mapping)
+        .recover(handle_rejection)
 }
 
 #[tokio::test]
@@ -89,6 +161,19 @@ async fn test_add_document_api() {
 
     let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
     assert_eq!(response_data["status"], "success");
+    assert_eq!(response_data["result"], "created");
+
+    let response = request()
+        .method("POST")
+        .path("/document")
+        .json(&doc)
+        .reply(&api)
+        .await;
+
+    assert_eq!(response.status(), 200);
+
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["result"], "updated");
 }
 
 #[tokio::test]
@@ -105,7 +190,7 @@ async fn test_search_api() {
 
     let add_response = request()
         .method("POST")
-        .path("/document")
+        .path("/document?refresh=wait_for")
         .json(&doc)
         .reply(&api)
         .await;
@@ -161,3 +246,1004 @@ async fn test_invalid_document() {
     assert_eq!(response_data["code"], 400);
     assert_eq!(response_data["error_type"], "validation_error");
 }
+
+#[tokio::test]
+async fn test_delete_document_api() {
+    let api = create_test_filter().await;
+
+    let doc = json!({
+        "id": "delete1",
+        "content": "Document that will be deleted",
+        "metadata": {}
+    });
+
+    let add_response = request()
+        .method("POST")
+        .path("/document")
+        .json(&doc)
+        .reply(&api)
+        .await;
+    assert_eq!(add_response.status(), 201);
+
+    let response = request()
+        .method("DELETE")
+        .path("/document/delete1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response = request()
+        .method("DELETE")
+        .path("/document/delete1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["status"], "error");
+}
+
+#[tokio::test]
+async fn test_bulk_api() {
+    let api = create_test_filter().await;
+
+    let body = [
+        r#"{"index": {"_id": "bulk1"}}"#,
+        r#"{"content": "Bulk indexed document", "metadata": {}}"#,
+        r#"{"delete": {"_id": "absent"}}"#,
+    ]
+    .join("\n");
+
+    let response = request()
+        .method("POST")
+        .path("/document/_bulk")
+        .body(body)
+        .reply(&api)
+        .await;
+
+    assert_eq!(response.status(), 200);
+
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["errors"], true);
+    assert_eq!(response_data["items"][0]["index"]["_id"], "bulk1");
+    assert_eq!(response_data["items"][0]["index"]["status"], 201);
+    assert_eq!(response_data["items"][0]["index"]["result"], "created");
+    assert_eq!(response_data["items"][1]["delete"]["status"], 404);
+
+    let response = request()
+        .method("POST")
+        .path("/document/_bulk")
+        .body("not json")
+        .reply(&api)
+        .await;
+
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_get_document_api() {
+    let api = create_test_filter().await;
+
+    let doc = json!({
+        "id": "get1",
+        "content": "Document fetched by id",
+        "metadata": {
+            "author": "Getter",
+            "category": "fetch"
+        }
+    });
+
+    request()
+        .method("POST")
+        .path("/document")
+        .json(&doc)
+        .reply(&api)
+        .await;
+
+    let response = request()
+        .method("GET")
+        .path("/document/get1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["document"]["content"], doc["content"]);
+    assert_eq!(response_data["document"]["metadata"], doc["metadata"]);
+    assert_eq!(response_data["document"]["_version"], 1);
+
+    let response = request()
+        .method("GET")
+        .path("/document/get1?fields=category")
+        .reply(&api)
+        .await;
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        response_data["document"],
+        json!({
+            "id": "get1",
+            "_version": 1,
+            "_seq_no": 1,
+            "metadata": { "category": "fetch" }
+        })
+    );
+
+    let response = request()
+        .method("GET")
+        .path("/document/missing")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+
+    let response = request()
+        .method("HEAD")
+        .path("/document/get1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    assert!(response.body().is_empty());
+
+    let response = request()
+        .method("HEAD")
+        .path("/document/missing")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_update_document_api() {
+    let api = create_test_filter().await;
+
+    let doc = json!({
+        "id": "update1",
+        "content": "Document to update",
+        "metadata": {
+            "category": "old"
+        }
+    });
+
+    request()
+        .method("POST")
+        .path("/document")
+        .json(&doc)
+        .reply(&api)
+        .await;
+
+    let response = request()
+        .method("POST")
+        .path("/document/update1/_update")
+        .json(&json!({ "metadata": { "category": "new" } }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["document"]["metadata"]["category"], "new");
+    assert_eq!(response_data["document"]["content"], "Document to update");
+
+    let response = request()
+        .method("POST")
+        .path("/document/missing/_update")
+        .json(&json!({ "metadata": { "category": "new" } }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_encoded_document_id_api() {
+    let api = create_test_filter().await;
+
+    let doc = json!({
+        "id": "reports/2024 q1",
+        "content": "Quarterly report",
+        "metadata": {
+            "category": "old"
+        }
+    });
+
+    request()
+        .method("POST")
+        .path("/document")
+        .json(&doc)
+        .reply(&api)
+        .await;
+
+    let response = request()
+        .method("GET")
+        .path("/document/reports%2F2024%20q1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["document"]["id"], "reports/2024 q1");
+
+    let response = request()
+        .method("HEAD")
+        .path("/document/reports%2F2024%20q1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response = request()
+        .method("POST")
+        .path("/document/reports%2F2024%20q1/_update")
+        .json(&json!({ "metadata": { "category": "new" } }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["document"]["metadata"]["category"], "new");
+
+    let response = request()
+        .method("DELETE")
+        .path("/document/reports%2F2024%20q1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response = request()
+        .method("GET")
+        .path("/document/reports%2F2024%20q1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 404);
+
+    let response = request()
+        .method("GET")
+        .path("/document/%FF")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_version_conflict_api() {
+    let api = create_test_filter().await;
+
+    let doc = json!({
+        "id": "versioned1",
+        "content": "Versioned document",
+        "metadata": {}
+    });
+
+    let response = request()
+        .method("POST")
+        .path("/document")
+        .json(&doc)
+        .reply(&api)
+        .await;
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["_version"], 1);
+
+    let response = request()
+        .method("POST")
+        .path("/document?if_version=1")
+        .json(&doc)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["_version"], 2);
+
+    let response = request()
+        .method("POST")
+        .path("/document/versioned1/_update?if_version=1")
+        .json(&json!({ "content": "Stale write" }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 409);
+
+    let response = request()
+        .method("DELETE")
+        .path("/document/versioned1?if_version=abc")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+
+    let response = request()
+        .method("GET")
+        .path("/document/versioned1")
+        .reply(&api)
+        .await;
+    let response_data: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(response_data["document"]["_version"], 2);
+    assert_eq!(response_data["document"]["content"], "Versioned document");
+}
+
+#[tokio::test]
+async fn test_mapping_api() {
+    let api = create_test_filter().await;
+
+    let response = request()
+        .method("PUT")
+        .path("/_mapping")
+        .json(&json!({
+            "properties": {
+                "price": { "type": "float", "fast": true },
+                "sku": { "type": "keyword" }
+            }
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response = request().method("GET").path("/_mapping").reply(&api).await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["mapping"]["properties"]["price"]["type"], "double");
+    assert_eq!(body["mapping"]["properties"]["price"]["fast"], true);
+    assert_eq!(body["mapping"]["properties"]["sku"]["type"], "keyword");
+    assert!(body["mapping"].get("generation").is_none());
+
+    let response = request()
+        .method("PUT")
+        .path("/_mapping")
+        .json(&json!({
+            "properties": { "price": { "type": "long", "analyzer": "russian" } }
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+
+    let response = request()
+        .method("POST")
+        .path("/document")
+        .json(&json!({
+            "id": "bad-price",
+            "content": "Item with a broken price",
+            "metadata": { "price": "cheap" }
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_pagination_api() {
+    let api = create_test_filter().await;
+
+    for i in 0..5 {
+        let response = request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&create_test_document(
+                &format!("p{}", i),
+                "paginated result",
+            ))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 201);
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=paginated&size=2&from=4")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 5);
+    assert_eq!(body["count"], 1);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=paginated&size=3")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    let cursor = body["search_after"].as_array().unwrap();
+    let path = format!(
+        "/search?q=paginated&size=3&search_after={},{}",
+        cursor[0], cursor[1]
+    );
+    let response = request().method("GET").path(&path).reply(&api).await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 5);
+    assert_eq!(body["count"], 2);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=paginated&size=many")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=paginated&from=18446744073709551615")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_scores_api() {
+    let api = create_test_filter().await;
+
+    for (id, content) in [("sc1", "scored scored scored"), ("sc2", "scored once here")] {
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&create_test_document(id, content))
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=scored")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["results"][0]["id"], "sc1");
+    assert_eq!(body["results"][0]["_rank"], 1);
+    assert_eq!(body["max_score"], body["results"][0]["_score"]);
+
+    let cutoff = body["results"][0]["_score"].as_f64().unwrap();
+    let response = request()
+        .method("GET")
+        .path(&format!("/search?q=scored&min_score={}", cutoff))
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["results"][0]["id"], "sc1");
+}
+
+#[tokio::test]
+async fn test_search_highlight_api() {
+    let api = create_test_filter().await;
+
+    request()
+        .method("POST")
+        .path("/document?refresh=true")
+        .json(&create_test_document(
+            "h1",
+            "The quick brown fox jumps over the lazy dog",
+        ))
+        .reply(&api)
+        .await;
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=fox&highlight=content,author&pre_tag=%3Cb%3E&post_tag=%3C/b%3E")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body["results"][0]["highlight"]["content"][0],
+        "The quick brown <b>fox</b> jumps over the lazy dog"
+    );
+    assert!(body["results"][0]["highlight"].get("author").is_none());
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=fox&highlight=nope")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_sort_api() {
+    let api = create_test_filter().await;
+
+    for (id, price) in [("cheap", 5), ("pricey", 50), ("middle", 20)] {
+        let mut doc = create_test_document(id, "sortable item");
+        doc.metadata.insert("price".to_string(), json!(price));
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&doc)
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=sortable&sort=price:desc")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    let ids: Vec<&str> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["pricey", "middle", "cheap"]);
+    assert_eq!(body["results"][0]["sort"][0], 50);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=sortable&sort=author:asc")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=sortable&sort=content:asc")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert!(body["message"].as_str().unwrap().contains("not sortable"));
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=sortable&sort=price:sideways")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_dsl_api() {
+    let api = create_test_filter().await;
+
+    for (id, content, year) in [
+        ("dsl1", "structured query languages", 2021),
+        ("dsl2", "structured data pipelines", 2023),
+        ("dsl3", "unstructured notes", 2024),
+    ] {
+        let mut doc = create_test_document(id, content);
+        doc.metadata.insert("year".to_string(), json!(year));
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&doc)
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({
+            "query": {
+                "bool": {
+                    "must": [{ "match": { "content": "structured" } }],
+                    "filter": [{ "range": { "year": { "gte": 2022 } } }]
+                }
+            },
+            "sort": [{ "year": "desc" }],
+            "size": 5
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["results"][0]["id"], "dsl2");
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({ "size": 2 }))
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 3);
+    assert_eq!(body["count"], 2);
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({ "query": { "unknown_query": {} } }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_range_query_api() {
+    let api = create_test_filter().await;
+
+    for (id, price) in [("rq1", 8), ("rq2", 30), ("rq3", 80)] {
+        let mut doc = create_test_document(id, "priced offer");
+        doc.metadata.insert("price".to_string(), json!(price));
+        doc.metadata
+            .insert("published".to_string(), json!("2024-05-01T00:00:00Z"));
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&doc)
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=price:%5B10%20TO%2050%5D")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["results"][0]["id"], "rq2");
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=published:%3Enow-7d")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 0);
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({
+            "query": { "range": { "published": { "lt": "now", "gte": "2024-05-01T00:00:00Z||/M" } } }
+        }))
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 3);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=published:%3Enow-bad")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_aggregations_api() {
+    let api = create_test_filter().await;
+
+    for (id, genre) in [("ag1", "jazz"), ("ag2", "jazz"), ("ag3", "rock")] {
+        let mut doc = create_test_document(id, "vinyl record");
+        doc.metadata.insert("genre".to_string(), json!(genre));
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&doc)
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({
+            "query": { "match": { "content": "vinyl" } },
+            "size": 0,
+            "aggs": { "genres": { "terms": { "field": "genre" } } }
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["count"], 0);
+    assert_eq!(
+        body["aggregations"]["genres"]["buckets"],
+        json!([
+            { "key": "jazz", "doc_count": 2 },
+            { "key": "rock", "doc_count": 1 }
+        ])
+    );
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=vinyl&aggs=genre:1")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["aggregations"]["genre"]["buckets"][0]["key"], "jazz");
+    assert_eq!(body["aggregations"]["genre"]["sum_other_doc_count"], 1);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=vinyl&aggs=author")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body["aggregations"]["author"]["buckets"],
+        json!([{ "key": "Test Author", "doc_count": 3 }])
+    );
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=vinyl&aggs=content")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_analytics_api() {
+    let api = create_test_filter().await;
+
+    for (id, rating, published) in [
+        ("an1", 3, "2024-02-01T10:00:00Z"),
+        ("an2", 4, "2024-02-03T10:00:00Z"),
+        ("an3", 5, "2024-02-03T12:00:00Z"),
+    ] {
+        let mut doc = create_test_document(id, "album review");
+        doc.metadata.insert("rating".to_string(), json!(rating));
+        doc.metadata
+            .insert("published".to_string(), json!(published));
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&doc)
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({
+            "query": { "match": { "content": "review" } },
+            "aggs": {
+                "per_day": {
+                    "date_histogram": { "field": "published", "calendar_interval": "day" },
+                    "aggs": { "rating": { "stats": { "field": "rating" } } }
+                },
+                "distinct_ratings": { "cardinality": { "field": "rating" } }
+            }
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    let days = &body["aggregations"]["per_day"]["buckets"];
+    assert_eq!(days.as_array().unwrap().len(), 3);
+    assert_eq!(days[0]["key_as_string"], "2024-02-01T00:00:00Z");
+    assert_eq!(days[1]["doc_count"], 0);
+    assert_eq!(days[2]["rating"]["avg"], 4.5);
+    assert_eq!(body["aggregations"]["distinct_ratings"]["value"], 3);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=review&aggs=%7B%22top%22%3A%7B%22max%22%3A%7B%22field%22%3A%22rating%22%7D%7D%7D")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["aggregations"]["top"]["value"], 5.0);
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({ "aggs": { "bad": { "sum": { "field": "content" } } } }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_facet_navigation_api() {
+    let api = create_test_filter().await;
+
+    request()
+        .method("PUT")
+        .path("/_mapping")
+        .json(&json!({ "properties": { "shelf": { "type": "facet" } } }))
+        .reply(&api)
+        .await;
+    for (id, shelf) in [
+        ("fc1", "/garden/tools/shovels"),
+        ("fc2", "/garden/tools/rakes"),
+        ("fc3", "/garden/plants"),
+        ("fc4", "/kitchen/knives"),
+    ] {
+        let mut doc = create_test_document(id, "store product");
+        doc.metadata.insert("shelf".to_string(), json!(shelf));
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&doc)
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=product&facet=shelf:/garden")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 4);
+    assert_eq!(
+        body["aggregations"]["shelf"]["buckets"],
+        json!([
+            { "key": "/garden/tools", "doc_count": 2 },
+            { "key": "/garden/plants", "doc_count": 1 }
+        ])
+    );
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({
+            "query": { "term": { "shelf": "/garden/tools" } },
+            "aggs": { "tools": { "facet": { "field": "shelf", "path": "/garden/tools" } } }
+        }))
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 2);
+    assert_eq!(
+        body["aggregations"]["tools"]["buckets"][0]["key"],
+        "/garden/tools/rakes"
+    );
+    assert_eq!(
+        body["aggregations"]["tools"]["buckets"][1]["key"],
+        "/garden/tools/shovels"
+    );
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=product&facet=shelf:garden")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_fuzzy_search_api() {
+    let api = create_test_filter().await;
+
+    for (id, content) in [("fz1", "quantum computing"), ("fz2", "classical music")] {
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&create_test_document(id, content))
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=qantum~1")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["results"][0]["id"], "fz1");
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=uantum~1&fuzzy_prefix_length=2")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 0);
+
+    let response = request()
+        .method("POST")
+        .path("/search")
+        .json(&json!({
+            "query": { "match": { "content": { "query": "clasical", "fuzziness": "auto", "prefix_length": 2 } } }
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["results"][0]["id"], "fz2");
+}
+
+#[tokio::test]
+async fn test_suggest_api() {
+    let api = create_test_filter().await;
+
+    for (id, content, category) in [
+        ("sg1", "rust programming", "books"),
+        ("sg2", "rusty nails", "hardware"),
+        ("sg3", "rust belt history", "books"),
+    ] {
+        let mut doc = create_test_document(id, content);
+        doc.metadata.insert("category".to_string(), json!(category));
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&doc)
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/suggest?prefix=ru")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["status"], "success");
+    assert_eq!(body["suggestions"][0]["text"], "rust");
+    assert_eq!(body["suggestions"][0]["doc_count"], 2);
+    assert_eq!(body["suggestions"][1]["text"], "rusty");
+
+    let response = request()
+        .method("GET")
+        .path("/suggest?prefix=ru&category=hardware&size=1")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 1);
+    assert_eq!(body["suggestions"][0]["text"], "rusty");
+
+    for path in [
+        "/suggest",
+        "/suggest?prefix=ru&size=0",
+        "/suggest?prefix=ru&fields=nope",
+    ] {
+        let response = request().method("GET").path(path).reply(&api).await;
+        assert_eq!(response.status(), 400);
+    }
+}
+
+#[tokio::test]
+async fn test_spelling_suggestions_api() {
+    let api = create_test_filter().await;
+
+    for (id, content) in [("sp1", "wireless keyboard"), ("sp2", "wireless mouse")] {
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&create_test_document(id, content))
+            .reply(&api)
+            .await;
+    }
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=wirless%20keybord&suggest=true")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["total"], 0);
+    assert_eq!(body["suggestions"][0]["text"], "wireless keyboard");
+    assert_eq!(body["suggestions"][0]["hits"], 2);
+
+    let response = request()
+        .method("GET")
+        .path("/search?q=wirless&suggest=maybe")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_multilingual_search_api() {
+    let api = create_test_filter().await;
+
+    for (id, content) in [
+        ("lg1", "Книги о базах данных"),
+        ("lg2", "Books about databases"),
+    ] {
+        request()
+            .method("POST")
+            .path("/document?refresh=true")
+            .json(&create_test_document(id, content))
+            .reply(&api)
+            .await;
+    }
+
+    for (query, expected) in [("%D0%BA%D0%BD%D0%B8%D0%B3%D0%B0", "lg1"), ("book", "lg2")] {
+        let response = request()
+            .method("GET")
+            .path(&format!("/search?q={}", query))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["results"][0]["id"], expected);
+    }
+}
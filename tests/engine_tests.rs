@@ -1,6 +1,19 @@
-use rust_search::common::config::Config;
+use rust_search::common::config::{Config, IndexingConfig};
+use rust_search::common::error::EngineError;
+use rust_search::core::aggregation::{parse_terms, AggregationResult};
+use rust_search::core::bulk::{parse_ndjson, BulkItemResponse};
+use rust_search::core::date_math;
+use rust_search::core::document::{Refresh, WriteOptions, WriteResult};
+use rust_search::core::dsl::QueryDsl;
+use rust_search::core::highlight::HighlightOptions;
+use rust_search::core::language::Language;
+use rust_search::core::mapping::{FieldKind, MappingUpdate};
+use rust_search::core::query::SearchOptions;
+use rust_search::core::sort::{parse_sort, SortField, SortOrder};
+use rust_search::core::suggest::SuggestRequest;
 use rust_search::{Document, SearchEngine};
-use std::collections::HashMap;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use tempfile::tempdir;
 
 fn create_test_config() -> Config {
@@ -17,18 +30,20 @@ fn create_test_config() -> Config {
             data_file: data_path.to_str().unwrap().to_string(),
             index_path: index_path.to_str().unwrap().to_string(),
         },
+        indexing: IndexingConfig::default(),
     }
 }
 
 fn create_test_document(id: &str, content: &str) -> Document {
     let mut metadata = HashMap::new();
-    metadata.insert("author".to_string(), "Test Author".to_string());
-    metadata.insert("type".to_string(), "test".to_string());
+    metadata.insert("author".to_string(), "Test Author".into());
+    metadata.insert("type".to_string(), "test".into());
 
     Document {
         id: id.to_string(),
         content: content.to_string(),
         metadata,
+        ..Default::default()
     }
 }
 
@@ -42,8 +57,8 @@ async fn test_add_and_search_document() {
 
     let results = engine.search("Rust").await.unwrap();
     assert_eq!(results.len(), 1);
-    assert_eq!(results[0].id, "1");
-    assert!(results[0].content.contains("Rust"));
+    assert_eq!(results[0].document.id, "1");
+    assert!(results[0].document.content.contains("Rust"));
 }
 
 #[tokio::test]
@@ -79,14 +94,14 @@ async fn test_metadata_search() {
 
     let mut doc = create_test_document("meta1", "Test document with metadata");
     doc.metadata
-        .insert("category".to_string(), "test_category".to_string());
+        .insert("category".to_string(), "test_category".into());
 
     engine.add_document(doc).await.unwrap();
 
     let results = engine.search("category:test_category").await.unwrap();
     assert!(!results.is_empty());
     assert_eq!(
-        results[0].metadata.get("category").unwrap(),
+        results[0].document.metadata.get("category").unwrap(),
         "test_category"
     );
 
@@ -109,28 +124,1926 @@ async fn test_metadata_search_with_field_syntax() -> anyhow::Result<()> {
         id: "test1".to_string(),
         content: "test content".to_string(),
         metadata: HashMap::new(),
+        ..Default::default()
     };
     doc1.metadata
-        .insert("author".to_string(), "John Doe".to_string());
+        .insert("author".to_string(), "John Doe".into());
 
     let mut doc2 = Document {
         id: "test2".to_string(),
         content: "other content".to_string(),
         metadata: HashMap::new(),
+        ..Default::default()
     };
     doc2.metadata
-        .insert("author".to_string(), "Jane Smith".to_string());
+        .insert("author".to_string(), "Jane Smith".into());
 
     engine.add_document(doc1.clone()).await?;
     engine.add_document(doc2.clone()).await?;
 
     let results = engine.search_with_metadata("John", &["author"]).await?;
     assert_eq!(results.len(), 1);
-    assert_eq!(results[0].id, "test1");
+    assert_eq!(results[0].document.id, "test1");
 
     let results = engine.search_with_metadata("Jane", &["author"]).await?;
     assert_eq!(results.len(), 1);
-    assert_eq!(results[0].id, "test2");
+    assert_eq!(results[0].document.id, "test2");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_document() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    engine
+        .add_document(create_test_document("del1", "Document to be removed"))
+        .await?;
+    engine
+        .add_document(create_test_document("del2", "Document to be kept"))
+        .await?;
+
+    engine.delete_document("del1").await?;
+
+    let results = engine.search("document").await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].document.id, "del2");
+
+    let err = engine.delete_document("del1").await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::DocumentNotFound(_))
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_loads_legacy_storage() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let data_file = std::path::Path::new(&config.storage.data_file);
+    std::fs::create_dir_all(data_file.parent().unwrap())?;
+
+    let mut legacy = HashMap::new();
+    for (id, content) in [("old1", "legacy apple"), ("old2", "legacy banana")] {
+        let metadata = HashMap::from([("author".to_string(), "alice".to_string())]);
+        legacy.insert(
+            id.to_string(),
+            (id.to_string(), content.to_string(), metadata),
+        );
+    }
+    std::fs::write(data_file, bincode::serialize(&legacy)?)?;
+
+    let engine = SearchEngine::new(&config)?;
+    let doc = engine.get_document("old1").await.unwrap();
+    assert_eq!(doc.version, 1);
+    assert_eq!(doc.metadata["author"], json!("alice"));
+    assert_eq!(engine.search("legacy").await?.len(), 2);
+    drop(engine);
+
+    let broken = create_test_config();
+    let data_file = std::path::Path::new(&broken.storage.data_file);
+    std::fs::create_dir_all(data_file.parent().unwrap())?;
+    std::fs::write(data_file, b"not a document store")?;
+
+    assert!(SearchEngine::new(&broken).is_err());
+    assert_eq!(std::fs::read(data_file)?, b"not a document store");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reindex_same_id_replaces_document() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let result = engine
+        .add_document(create_test_document("up1", "original apple text"))
+        .await?;
+    assert_eq!(result.result, WriteResult::Created);
+
+    let result = engine
+        .add_document(create_test_document("up1", "replacement banana text"))
+        .await?;
+    assert_eq!(result.result, WriteResult::Updated);
+
+    assert!(engine.search("apple").await?.is_empty());
+
+    let results = engine.search("banana").await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].document.id, "up1");
+
+    let results = engine.search("text").await?;
+    assert_eq!(results.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bulk_operations() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    engine
+        .add_document(create_test_document("old", "stale bulk entry"))
+        .await?;
+
+    let body = [
+        r#"{"index": {"_id": "b1"}}"#,
+        r#"{"content": "first bulk entry", "metadata": {}}"#,
+        r#"{"index": {}}"#,
+        r#"{"id": "b2", "content": "second bulk entry", "metadata": {}}"#,
+        r#"{"index": {"_id": "b3"}}"#,
+        r#"{"content": 42}"#,
+        r#"{"delete": {"_id": "old"}}"#,
+        r#"{"delete": {"_id": "missing"}}"#,
+    ]
+    .join("\n");
+
+    let items = engine.bulk(parse_ndjson(&body)?).await?;
+    let statuses: Vec<u16> = items.iter().map(|item| item.status().status).collect();
+    assert_eq!(statuses, vec![201, 201, 400, 200, 404]);
+    assert!(matches!(items[3], BulkItemResponse::Delete(_)));
+    assert_eq!(items[4].status().result, Some(WriteResult::NotFound));
+
+    let results = engine.search("bulk").await?;
+    let mut ids: Vec<String> = results.into_iter().map(|hit| hit.document.id).collect();
+    ids.sort();
+    assert_eq!(ids, vec!["b1", "b2"]);
+
+    assert!(parse_ndjson(r#"{"upsert": {"_id": "x"}}"#).is_err());
+
+    let before = engine
+        .add_document(create_test_document("b4", "fourth bulk entry"))
+        .await?;
+    let next_generation = std::path::Path::new(&config.storage.index_path)
+        .join(format!("gen-{}", engine.mapping().generation + 1));
+    std::fs::write(&next_generation, b"")?;
+    let body = [
+        r#"{"index": {"_id": "ghost"}}"#,
+        r#"{"content": "ghost bulk entry", "metadata": {"fresh": 1}}"#,
+        r#"{"delete": {"_id": "ghost"}}"#,
+        r#"{"delete": {"_id": "b4"}}"#,
+    ]
+    .join("\n");
+    assert!(engine.bulk(parse_ndjson(&body)?).await.is_err());
+    std::fs::remove_file(&next_generation)?;
+
+    assert!(engine.get_document("ghost").await.is_none());
+    assert_eq!(engine.get_document("b4").await.unwrap().version, 1);
+    let ghost = engine
+        .add_document(create_test_document("ghost", "ghost entry"))
+        .await?;
+    assert_eq!(ghost.version, 1);
+    assert_eq!(ghost.seq_no, before.seq_no + 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_update_document_merges_metadata() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let mut doc = create_test_document("patch1", "Document with patched metadata");
+    doc.metadata.insert("category".to_string(), "drafts".into());
+    engine.add_document(doc).await?;
+
+    let updated = engine
+        .update_document(
+            "patch1",
+            &json!({ "metadata": { "category": "published", "type": null } }),
+        )
+        .await?;
+
+    assert_eq!(updated.content, "Document with patched metadata");
+    assert_eq!(updated.metadata.get("category").unwrap(), "published");
+    assert_eq!(updated.metadata.get("author").unwrap(), "Test Author");
+    assert!(!updated.metadata.contains_key("type"));
+
+    assert!(engine.search("category:drafts").await?.is_empty());
+    assert_eq!(engine.search("category:published").await?.len(), 1);
+
+    let err = engine
+        .update_document("missing", &json!({ "content": "x" }))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::DocumentNotFound(_))
+    ));
+
+    let err = engine
+        .update_document("patch1", &json!({ "content": null }))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::InvalidDocument(_))
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_document_versions() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let first = engine
+        .add_document(create_test_document("v1", "first version"))
+        .await?;
+    assert_eq!(first.version, 1);
+
+    let second = engine
+        .add_document(create_test_document("v1", "second version"))
+        .await?;
+    assert_eq!(second.version, 2);
+    assert!(second.seq_no > first.seq_no);
+
+    let stale = WriteOptions {
+        if_version: Some(1),
+        ..WriteOptions::immediate()
+    };
+    let err = engine
+        .add_document_with_options(create_test_document("v1", "stale version"), &stale)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::VersionConflict {
+            expected: 1,
+            current: 2,
+            ..
+        })
+    ));
+
+    let current = WriteOptions {
+        if_version: Some(2),
+        ..WriteOptions::immediate()
+    };
+    let updated = engine
+        .update_document_with_options("v1", &json!({ "content": "third version" }), &current)
+        .await?;
+    assert_eq!(updated.version, 3);
+
+    let results = engine.search("version").await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].document.version, 3);
+    assert_eq!(results[0].document.content, "third version");
+
+    let err = engine
+        .delete_document_with_options("v1", &stale)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::VersionConflict { .. })
+    ));
+
+    let deleted = engine.delete_document("v1").await?;
+    assert_eq!(deleted.version, 4);
+
+    let err = engine
+        .add_document_with_options(create_test_document("v1", "stale version"), &stale)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::VersionConflict {
+            expected: 1,
+            current: 0,
+            ..
+        })
+    ));
+
+    let recreated = engine
+        .add_document(create_test_document("v1", "recreated version"))
+        .await?;
+    assert_eq!(recreated.result, WriteResult::Created);
+    assert_eq!(recreated.version, 5);
+
+    let err = engine
+        .add_document_with_options(create_test_document("v1", "stale version"), &stale)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::VersionConflict {
+            expected: 1,
+            current: 5,
+            ..
+        })
+    ));
+
+    let newest = engine
+        .add_document(create_test_document("v2", "newest version"))
+        .await?;
+    let deleted = engine.delete_document("v2").await?;
+    assert!(deleted.seq_no > newest.seq_no);
+    drop(engine);
+
+    let reloaded = SearchEngine::new(&config)?;
+    let next = reloaded
+        .add_document(create_test_document("v3", "after restart"))
+        .await?;
+    assert!(next.seq_no > deleted.seq_no);
+    let recreated = reloaded
+        .add_document(create_test_document("v2", "recreated after restart"))
+        .await?;
+    assert_eq!(recreated.version, 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refresh_controls_visibility() -> anyhow::Result<()> {
+    let mut config = create_test_config();
+    config.indexing = IndexingConfig {
+        refresh_interval: "-1".to_string(),
+        max_buffered_docs: 3,
+        ..Default::default()
+    };
+    let engine = SearchEngine::new(&config)?;
+
+    let deferred = WriteOptions::default();
+    engine
+        .add_document_with_options(create_test_document("r1", "buffered entry"), &deferred)
+        .await?;
+    assert!(engine.search("buffered").await?.is_empty());
+    assert!(engine.get_document("r1").await.is_some());
+
+    engine.refresh().await?;
+    assert_eq!(engine.search("buffered").await?.len(), 1);
+
+    for i in 2..=4 {
+        engine
+            .add_document_with_options(
+                create_test_document(&format!("r{}", i), "buffered entry"),
+                &deferred,
+            )
+            .await?;
+    }
+    assert_eq!(engine.search("buffered").await?.len(), 4);
+
+    let wait_for = WriteOptions {
+        refresh: Refresh::WaitFor,
+        ..WriteOptions::default()
+    };
+    engine
+        .add_document_with_options(create_test_document("r5", "buffered entry"), &wait_for)
+        .await?;
+    assert_eq!(engine.search("buffered").await?.len(), 5);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_uncommitted_writes_survive_restart() -> anyhow::Result<()> {
+    let mut config = create_test_config();
+    config.indexing = IndexingConfig {
+        refresh_interval: "-1".to_string(),
+        ..Default::default()
+    };
+    let engine = SearchEngine::new(&config)?;
+
+    engine
+        .add_document(create_test_document("1", "rust systems"))
+        .await?;
+    engine
+        .add_document(create_test_document("2", "python scripting"))
+        .await?;
+
+    let deferred = WriteOptions::default();
+    engine
+        .add_document_with_options(create_test_document("3", "golang services"), &deferred)
+        .await?;
+    engine
+        .add_document_with_options(create_test_document("1", "rust compilers"), &deferred)
+        .await?;
+    engine.delete_document_with_options("2", &deferred).await?;
+    drop(engine);
+
+    let reloaded = SearchEngine::new(&config)?;
+    let ids = |query: &'static str| {
+        let engine = reloaded.clone();
+        async move {
+            let options = SearchOptions::default();
+            let results = engine.search_with_options(query, &options).await?;
+            let ids: Vec<String> = results
+                .hits
+                .into_iter()
+                .map(|hit| hit.document.id)
+                .collect();
+            anyhow::Ok((results.total, ids))
+        }
+    };
+    assert_eq!(ids("golang").await?, (1, vec!["3".to_string()]));
+    assert_eq!(ids("compilers").await?, (1, vec!["1".to_string()]));
+    assert_eq!(ids("systems").await?.0, 0);
+    assert_eq!(ids("python").await?.0, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_typed_metadata_round_trip() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let mut doc = create_test_document("typed1", "Document with typed metadata");
+    doc.metadata.insert("category".to_string(), json!(42));
+    doc.metadata
+        .insert("author".to_string(), json!(["Alice Cooper", "Bob Dylan"]));
+    doc.metadata.insert("price".to_string(), json!(19.99));
+    doc.metadata.insert("in_stock".to_string(), json!(true));
+    doc.metadata
+        .insert("published".to_string(), json!("2024-03-01T12:00:00Z"));
+    doc.metadata.insert(
+        "dimensions".to_string(),
+        json!({ "width": 10, "unit": "cm" }),
+    );
+    engine.add_document(doc.clone()).await?;
+
+    assert_eq!(engine.search("category:42").await?.len(), 1);
+    assert_eq!(engine.search("author:alice").await?.len(), 1);
+    assert_eq!(engine.search("author:dylan").await?.len(), 1);
+    drop(engine);
+
+    let reloaded = SearchEngine::new(&config)?;
+    let stored = reloaded.get_document("typed1").await.unwrap();
+    assert_eq!(stored.metadata, doc.metadata);
+    assert_eq!(stored.metadata["price"], json!(19.99));
+    assert_eq!(stored.metadata["dimensions"]["unit"], "cm");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dynamic_metadata_mapping() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    engine
+        .add_document(create_test_document("dyn1", "Plain document"))
+        .await?;
+
+    let mut doc = create_test_document("dyn2", "Document with new metadata keys");
+    doc.metadata.insert("color".to_string(), json!("red"));
+    doc.metadata.insert("rating".to_string(), json!(5));
+    doc.metadata
+        .insert("published".to_string(), json!("2024-03-01T12:00:00Z"));
+    doc.metadata
+        .insert("dimensions".to_string(), json!({ "unit": "cm" }));
+    engine.add_document(doc).await?;
+
+    let mapping = engine.mapping();
+    assert_eq!(mapping.properties["color"].kind, FieldKind::Text);
+    assert_eq!(mapping.properties["rating"].kind, FieldKind::Long);
+    assert_eq!(mapping.properties["published"].kind, FieldKind::Date);
+    assert_eq!(mapping.properties["dimensions.unit"].kind, FieldKind::Text);
+
+    assert_eq!(engine.search("color:red").await?.len(), 1);
+    assert_eq!(engine.search("rating:5").await?.len(), 1);
+    assert_eq!(engine.search("dimensions.unit:cm").await?.len(), 1);
+    assert_eq!(engine.search("document").await?.len(), 2);
+    drop(engine);
+
+    let reloaded = SearchEngine::new(&config)?;
+    assert_eq!(reloaded.mapping(), mapping);
+    assert_eq!(reloaded.search("color:red").await?.len(), 1);
+
+    reloaded.add_metadata_field("notes").await?;
+    assert!(reloaded.mapping().properties.contains_key("notes"));
+    assert_eq!(reloaded.search("document").await?.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_metadata_field_names() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    for key in ["content", "id", "", "-x"] {
+        let mut doc = create_test_document("bad", "Document with a reserved key");
+        doc.metadata.insert(key.to_string(), json!("value"));
+        let err = engine.add_document(doc).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineError>(),
+            Some(EngineError::InvalidDocument(_))
+        ));
+    }
+
+    engine
+        .add_document(create_test_document("good", "Document still writable"))
+        .await?;
+    assert_eq!(engine.search("writable").await?.len(), 1);
+    assert!(!engine.mapping().properties.contains_key("-x"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_explicit_mapping() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+    engine
+        .add_document(create_test_document("map0", "Existing document"))
+        .await?;
+
+    let update: MappingUpdate = serde_json::from_value(json!({
+        "dynamic": false,
+        "properties": {
+            "price": { "type": "float", "fast": true },
+            "sku": { "type": "keyword" },
+            "title": { "type": "text", "analyzer": "russian" }
+        }
+    }))?;
+    let mapping = engine.put_mapping(update).await?;
+    assert!(!mapping.dynamic);
+    assert_eq!(mapping.properties["price"].kind, FieldKind::Double);
+    assert_eq!(
+        mapping.properties["title"].analyzer.as_deref(),
+        Some("russian")
+    );
+
+    let mut doc = create_test_document("map1", "Учебник");
+    doc.metadata.insert("price".to_string(), json!(12.5));
+    doc.metadata.insert("sku".to_string(), json!("AB-1 X"));
+    doc.metadata
+        .insert("title".to_string(), json!("Грамматика русского языка"));
+    doc.metadata.insert("extra".to_string(), json!("ignored"));
+    engine.add_document(doc).await?;
+
+    assert_eq!(engine.search("title:язык").await?.len(), 1);
+    assert_eq!(engine.search("sku:\"AB-1 X\"").await?.len(), 1);
+    assert_eq!(engine.search("sku:ab").await?.len(), 0);
+    assert!(!engine.mapping().properties.contains_key("extra"));
+    assert_eq!(engine.search("existing").await?.len(), 1);
+
+    let mut bad = create_test_document("map2", "Broken price");
+    bad.metadata.insert("price".to_string(), json!("cheap"));
+    let error = engine.add_document(bad).await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<EngineError>(),
+        Some(EngineError::InvalidDocument(_))
+    ));
+
+    let invalid: MappingUpdate = serde_json::from_value(json!({
+        "properties": { "sku": { "type": "keyword", "analyzer": "missing" } }
+    }))?;
+    assert!(engine.put_mapping(invalid).await.is_err());
+
+    let conflicting: MappingUpdate = serde_json::from_value(json!({
+        "properties": { "sku": { "type": "long" } }
+    }))?;
+    assert!(engine.put_mapping(conflicting).await.is_err());
+    assert_eq!(engine.mapping(), mapping);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_pagination() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let body: String = (0..25)
+        .map(|i| {
+            format!(
+                "{}\n{}\n",
+                json!({ "index": { "_id": format!("page{}", i) } }),
+                json!({ "content": "paged document", "metadata": {} })
+            )
+        })
+        .collect();
+    engine.bulk(parse_ndjson(&body)?).await?;
+
+    assert_eq!(engine.search("paged").await?.len(), 10);
+
+    let mut seen = HashSet::new();
+    for from in [0, 10, 20] {
+        let options = SearchOptions {
+            from,
+            ..Default::default()
+        };
+        let page = engine.search_with_options("paged", &options).await?;
+        assert_eq!(page.total, 25);
+        seen.extend(page.hits.into_iter().map(|hit| hit.document.id));
+    }
+    assert_eq!(seen.len(), 25);
+
+    let mut cursor_seen = Vec::new();
+    let mut options = SearchOptions {
+        size: 7,
+        ..Default::default()
+    };
+    loop {
+        let page = engine.search_with_options("paged", &options).await?;
+        if page.hits.is_empty() {
+            break;
+        }
+        cursor_seen.extend(page.hits.into_iter().map(|hit| hit.document.id));
+        options.search_after = page.search_after;
+    }
+    assert_eq!(cursor_seen.len(), 25);
+    assert_eq!(cursor_seen.iter().collect::<HashSet<_>>().len(), 25);
+
+    let invalid = SearchOptions {
+        from: 9_995,
+        size: 10,
+        ..Default::default()
+    };
+    let error = engine
+        .search_with_options("paged", &invalid)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<EngineError>(),
+        Some(EngineError::InvalidQuery(_))
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_scores() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    engine
+        .add_document(create_test_document(
+            "s1",
+            "rust rust rust systems programming",
+        ))
+        .await?;
+    engine
+        .add_document(create_test_document(
+            "s2",
+            "a long essay about gardening that mentions rust once among many other words",
+        ))
+        .await?;
+
+    let results = engine
+        .search_with_options("rust", &SearchOptions::default())
+        .await?;
+    assert_eq!(results.total, 2);
+    assert_eq!(results.hits[0].document.id, "s1");
+    assert_eq!(results.hits[0].rank, 1);
+    assert_eq!(results.hits[1].rank, 2);
+    assert!(results.hits[0].score > results.hits[1].score);
+    assert_eq!(results.max_score, Some(results.hits[0].score));
+
+    let threshold = SearchOptions {
+        min_score: Some((results.hits[0].score + results.hits[1].score) / 2.0),
+        ..Default::default()
+    };
+    let filtered = engine.search_with_options("rust", &threshold).await?;
+    assert_eq!(filtered.total, 1);
+    assert_eq!(filtered.hits.len(), 1);
+    assert_eq!(filtered.hits[0].document.id, "s1");
+
+    let single = engine
+        .search_with_options("gardening", &SearchOptions::default())
+        .await?;
+    assert_eq!(single.max_score.map(|score| score > 0.0), Some(true));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_highlighting() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let filler = "lorem ipsum dolor sit amet ".repeat(20);
+    let content = format!(
+        "Tantivy is fast. {}Search engines need highlighting. {}Tantivy powers this search.",
+        filler, filler
+    );
+    engine
+        .add_document(create_test_document("hl1", &content))
+        .await?;
+
+    let options = SearchOptions {
+        highlight: Some(HighlightOptions {
+            fields: vec!["content".to_string(), "author".to_string()],
+            fragment_size: 40,
+            number_of_fragments: 2,
+            pre_tag: "[".to_string(),
+            post_tag: "]".to_string(),
+        }),
+        ..Default::default()
+    };
+    let results = engine
+        .search_with_options("tantivy author:test", &options)
+        .await?;
+    let highlight = &results.hits[0].highlight;
+
+    let fragments = &highlight["content"];
+    assert_eq!(fragments.len(), 2);
+    assert!(fragments
+        .iter()
+        .all(|fragment| fragment.contains("[Tantivy]")));
+    assert!(fragments.iter().all(|fragment| fragment.len() <= 60));
+    assert_eq!(highlight["author"], vec!["[Test] Author".to_string()]);
+
+    let plain = engine
+        .search_with_options("tantivy", &SearchOptions::default())
+        .await?;
+    assert!(plain.hits[0].highlight.is_empty());
+
+    let invalid = SearchOptions {
+        highlight: Some(HighlightOptions {
+            fields: vec!["missing".to_string()],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    assert!(engine
+        .search_with_options("tantivy", &invalid)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_sorting() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let products = [
+        ("p1", Some(30.0), "2024-01-03T00:00:00Z", "tools"),
+        ("p2", Some(10.0), "2024-01-01T00:00:00Z", "books"),
+        ("p3", None, "2024-01-04T00:00:00Z", "books"),
+        ("p4", Some(10.0), "2024-01-02T00:00:00Z", "games"),
+    ];
+    for (id, price, published, category) in products {
+        let mut doc = create_test_document(id, "product listing");
+        if let Some(price) = price {
+            doc.metadata.insert("price".to_string(), json!(price));
+        }
+        doc.metadata
+            .insert("published".to_string(), json!(published));
+        doc.metadata.insert("shelf".to_string(), json!(category));
+        engine.add_document(doc).await?;
+    }
+    engine
+        .put_mapping(serde_json::from_value(json!({
+            "properties": { "shelf": { "type": "keyword" } }
+        }))?)
+        .await?;
+
+    let ids = |options: SearchOptions| {
+        let engine = engine.clone();
+        async move {
+            let results = engine.search_with_options("product", &options).await?;
+            anyhow::Ok(
+                results
+                    .hits
+                    .into_iter()
+                    .map(|hit| hit.document.id)
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+
+    let newest = SearchOptions {
+        sort: vec![SortField::new("published", SortOrder::Desc)],
+        ..Default::default()
+    };
+    assert_eq!(ids(newest).await?, vec!["p3", "p1", "p4", "p2"]);
+
+    let cheapest = SearchOptions {
+        sort: parse_sort("price:asc,published:desc")?,
+        ..Default::default()
+    };
+    assert_eq!(ids(cheapest.clone()).await?, vec!["p4", "p2", "p1", "p3"]);
+
+    let by_shelf = SearchOptions {
+        sort: parse_sort("shelf:desc,price:desc")?,
+        ..Default::default()
+    };
+    assert_eq!(ids(by_shelf).await?, vec!["p1", "p4", "p2", "p3"]);
+
+    let first_page = engine
+        .search_with_options(
+            "product",
+            &SearchOptions {
+                size: 2,
+                ..cheapest.clone()
+            },
+        )
+        .await?;
+    assert_eq!(first_page.hits[0].sort[0], json!(10.0));
+    let second_page = SearchOptions {
+        size: 2,
+        search_after: first_page.search_after,
+        ..cheapest
+    };
+    assert_eq!(ids(second_page).await?, vec!["p1", "p3"]);
+
+    for field in ["content", "missing"] {
+        let invalid = SearchOptions {
+            sort: vec![SortField::new(field, SortOrder::Asc)],
+            ..Default::default()
+        };
+        let error = engine
+            .search_with_options("product", &invalid)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<EngineError>(),
+            Some(EngineError::InvalidQuery(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_query_dsl() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+    engine
+        .put_mapping(serde_json::from_value(json!({
+            "properties": {
+                "sku": { "type": "keyword" },
+                "price": { "type": "double" },
+                "published": { "type": "date" }
+            }
+        }))?)
+        .await?;
+
+    let items = [
+        (
+            "d1",
+            "The quick brown fox",
+            "FOX-1",
+            Some(12.0),
+            "2024-01-10T00:00:00Z",
+        ),
+        (
+            "d2",
+            "A quick red fox jumps",
+            "FOX-2",
+            Some(25.0),
+            "2024-02-10T00:00:00Z",
+        ),
+        (
+            "d3",
+            "Brown bears are not quick",
+            "BEAR-1",
+            None,
+            "2024-03-10T00:00:00Z",
+        ),
+    ];
+    let categories = ["Web Development", "Data Science", "Web Design"];
+    for ((id, content, sku, price, published), category) in items.into_iter().zip(categories) {
+        let mut doc = create_test_document(id, content);
+        doc.metadata.insert("sku".to_string(), json!(sku));
+        doc.metadata.insert("category".to_string(), json!(category));
+        doc.metadata
+            .insert("published".to_string(), json!(published));
+        if let Some(price) = price {
+            doc.metadata.insert("price".to_string(), json!(price));
+        }
+        engine.add_document(doc).await?;
+    }
+
+    let search = |query: serde_json::Value| {
+        let engine = engine.clone();
+        async move {
+            let query: QueryDsl = serde_json::from_value(query)?;
+            let results = engine.search_dsl(&query, &SearchOptions::default()).await?;
+            let mut ids: Vec<String> = results
+                .hits
+                .into_iter()
+                .map(|hit| hit.document.id)
+                .collect();
+            ids.sort();
+            anyhow::Ok(ids)
+        }
+    };
+
+    assert_eq!(search(json!({ "match_all": {} })).await?.len(), 3);
+    assert_eq!(
+        search(json!({ "match": { "content": "brown fox" } })).await?,
+        vec!["d1", "d2", "d3"]
+    );
+    assert_eq!(
+        search(json!({ "match": { "content": { "query": "brown fox", "operator": "and" } } }))
+            .await?,
+        vec!["d1"]
+    );
+    assert_eq!(
+        search(json!({ "match_phrase": { "content": "quick brown" } })).await?,
+        vec!["d1"]
+    );
+    assert_eq!(
+        search(json!({ "term": { "sku": "FOX-2" } })).await?,
+        vec!["d2"]
+    );
+    assert_eq!(
+        search(json!({ "terms": { "sku": ["FOX-1", "BEAR-1"] } })).await?,
+        vec!["d1", "d3"]
+    );
+    assert_eq!(
+        search(json!({ "term": { "category": "Web Development" } })).await?,
+        vec!["d1"]
+    );
+    assert_eq!(
+        search(json!({ "terms": { "category": ["Data Science", "Web Design"] } })).await?,
+        vec!["d2", "d3"]
+    );
+    assert_eq!(
+        search(json!({ "match": { "category": "web" } })).await?,
+        vec!["d1", "d3"]
+    );
+    assert_eq!(
+        search(json!({ "range": { "price": { "gt": 12, "lte": 25 } } })).await?,
+        vec!["d2"]
+    );
+    assert_eq!(
+        search(json!({ "range": { "published": { "gte": "2024-02-01T00:00:00Z" } } })).await?,
+        vec!["d2", "d3"]
+    );
+    assert_eq!(
+        search(json!({ "exists": { "field": "price" } })).await?,
+        vec!["d1", "d2"]
+    );
+    assert_eq!(
+        search(json!({ "prefix": { "sku": "FOX" } })).await?,
+        vec!["d1", "d2"]
+    );
+    assert_eq!(
+        search(json!({ "wildcard": { "sku": "*-1" } })).await?,
+        vec!["d1", "d3"]
+    );
+    assert_eq!(
+        search(json!({
+            "bool": {
+                "must": { "match": { "content": "quick" } },
+                "filter": [{ "exists": { "field": "price" } }],
+                "must_not": [{ "term": { "sku": "FOX-2" } }]
+            }
+        }))
+        .await?,
+        vec!["d1"]
+    );
+    assert_eq!(
+        search(json!({ "bool": { "must_not": { "match": { "content": "fox" } } } })).await?,
+        vec!["d3"]
+    );
+    assert_eq!(
+        search(json!({
+            "bool": {
+                "should": [
+                    { "term": { "sku": "FOX-1" } },
+                    { "term": { "sku": "BEAR-1" } }
+                ]
+            }
+        }))
+        .await?,
+        vec!["d1", "d3"]
+    );
+    assert!(search(json!({ "term": { "unknown": "x" } }))
+        .await?
+        .is_empty());
+
+    let filtered: QueryDsl = serde_json::from_value(json!({
+        "bool": { "filter": { "term": { "sku": "FOX-1" } } }
+    }))?;
+    let results = engine
+        .search_dsl(&filtered, &SearchOptions::default())
+        .await?;
+    assert_eq!(results.hits[0].score, 0.0);
+
+    assert!(search(json!({ "range": { "price": { "gt": "cheap" } } }))
+        .await
+        .is_err());
+    assert!(serde_json::from_value::<QueryDsl>(json!({ "fuzzy": {} })).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_range_queries() -> anyhow::Result<()> {
+    use tantivy::time::format_description::well_known::Rfc3339;
+    use tantivy::time::{Duration, OffsetDateTime};
+
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let now = OffsetDateTime::now_utc();
+    for (id, price, age_days) in [("r1", 5.0, 2), ("r2", 25.5, 10), ("r3", 50.0, 40)] {
+        let published = (now - Duration::days(age_days)).format(&Rfc3339)?;
+        let mut doc = create_test_document(id, "ranged listing");
+        doc.metadata.insert("price".to_string(), json!(price));
+        doc.metadata
+            .insert("published".to_string(), json!(published));
+        engine.add_document(doc).await?;
+    }
+
+    let ids = |query: &'static str| {
+        let engine = engine.clone();
+        async move {
+            let mut ids: Vec<String> = engine
+                .search(query)
+                .await?
+                .into_iter()
+                .map(|hit| hit.document.id)
+                .collect();
+            ids.sort();
+            anyhow::Ok(ids)
+        }
+    };
+
+    assert_eq!(ids("price:[10 TO 50]").await?, vec!["r2", "r3"]);
+    assert_eq!(ids("price:{10 TO 50}").await?, vec!["r2"]);
+    assert_eq!(ids("price:>=25.5").await?, vec!["r2", "r3"]);
+    assert_eq!(ids("listing AND price:<10").await?, vec!["r1"]);
+    assert_eq!(ids("published:>now-7d").await?, vec!["r1"]);
+    assert_eq!(ids("published:[now-30d TO now]").await?, vec!["r1", "r2"]);
+    assert_eq!(ids("published:<now-1M").await?, vec!["r3"]);
+    assert!(engine.search("published:>now-7x").await.is_err());
+
+    let query: QueryDsl = serde_json::from_value(json!({
+        "bool": {
+            "filter": [
+                { "range": { "published": { "gte": "now-14d/d" } } },
+                { "range": { "price": { "gte": 10 } } }
+            ]
+        }
+    }))?;
+    let results = engine.search_dsl(&query, &SearchOptions::default()).await?;
+    assert_eq!(results.total, 1);
+    assert_eq!(results.hits[0].document.id, "r2");
+
+    let anchor = OffsetDateTime::parse("2024-01-31T10:30:00Z", &Rfc3339)?;
+    let resolved = date_math::resolve_at("now+1M/d", anchor)?;
+    assert_eq!(
+        resolved.into_utc().format(&Rfc3339)?,
+        "2024-02-29T00:00:00Z"
+    );
+    let resolved = date_math::resolve_at("2024-03-10T00:00:00Z||-1w", anchor)?;
+    assert_eq!(
+        resolved.into_utc().format(&Rfc3339)?,
+        "2024-03-03T00:00:00Z"
+    );
+
+    assert!(engine.search("published:вчера").await.is_err());
+    assert!(engine.search("published:>now-1д").await.is_err());
+    for expr in ["nowé", "now-1д", "now/д"] {
+        assert!(matches!(
+            date_math::resolve_at(expr, anchor),
+            Err(EngineError::InvalidQuery(_))
+        ));
+    }
+    for unit in ['y', 'M', 'w', 'd', 'h', 'H', 'm', 's'] {
+        for op in ['+', '-'] {
+            let expr = format!("now{}999999999999999999{}", op, unit);
+            assert!(matches!(
+                date_math::resolve_at(&expr, anchor),
+                Err(EngineError::InvalidQuery(_))
+            ));
+        }
+    }
+    assert!(engine
+        .search("published:>now+999999999999999999d")
+        .await
+        .is_err());
+    let query: QueryDsl = serde_json::from_value(json!({
+        "range": { "published": { "gte": "nowé" } }
+    }))?;
+    let err = engine
+        .search_dsl(&query, &SearchOptions::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<EngineError>(),
+        Some(EngineError::InvalidQuery(_))
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_terms_aggregations() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+    engine
+        .put_mapping(serde_json::from_value(json!({
+            "properties": {
+                "notes": { "type": "text", "fast": false }
+            }
+        }))?)
+        .await?;
+
+    let books = [
+        ("b1", "rust guide", "books", vec!["alice", "bob"]),
+        ("b2", "rust cookbook", "books", vec!["alice"]),
+        ("b3", "rust video course", "video", vec!["carol"]),
+        ("b4", "python guide", "books", vec!["dave"]),
+        ("b5", "rust stickers", "merch", vec![]),
+    ];
+    for (id, content, category, writers) in books {
+        let mut doc = create_test_document(id, content);
+        doc.metadata.insert("category".to_string(), json!(category));
+        doc.metadata.insert("writer".to_string(), json!(writers));
+        engine.add_document(doc).await?;
+    }
+
+    let options = SearchOptions {
+        size: 1,
+        aggs: serde_json::from_value(json!({
+            "categories": { "terms": { "field": "category" } },
+            "writers": { "terms": { "field": "writer", "size": 2 } }
+        }))?,
+        ..Default::default()
+    };
+    let results = engine.search_with_options("rust", &options).await?;
+    assert_eq!(results.total, 4);
+    assert_eq!(results.hits.len(), 1);
+
+    let AggregationResult::Buckets(categories) = &results.aggregations["categories"] else {
+        panic!("expected buckets");
+    };
+    let buckets: Vec<_> = categories
+        .buckets
+        .iter()
+        .map(|bucket| (bucket.key.clone(), bucket.doc_count))
+        .collect();
+    assert_eq!(
+        buckets,
+        vec![
+            (json!("books"), 2),
+            (json!("merch"), 1),
+            (json!("video"), 1)
+        ]
+    );
+    assert_eq!(categories.sum_other_doc_count, Some(0));
+
+    let AggregationResult::Buckets(writers) = &results.aggregations["writers"] else {
+        panic!("expected buckets");
+    };
+    assert_eq!(writers.buckets[0].key, json!("alice"));
+    assert_eq!(writers.buckets[0].doc_count, 2);
+    assert_eq!(writers.buckets[1].key, json!("bob"));
+    assert_eq!(writers.sum_other_doc_count, Some(1));
+
+    let options = SearchOptions {
+        aggs: parse_terms("category:1")?,
+        ..Default::default()
+    };
+    let results = engine.search_with_options("guide", &options).await?;
+    let AggregationResult::Buckets(categories) = &results.aggregations["category"] else {
+        panic!("expected buckets");
+    };
+    assert_eq!(categories.buckets.len(), 1);
+    assert_eq!(categories.buckets[0].key, json!("books"));
+    assert_eq!(categories.buckets[0].doc_count, 2);
+
+    let options = SearchOptions {
+        aggs: parse_terms("notes")?,
+        ..Default::default()
+    };
+    assert!(engine.search_with_options("rust", &options).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_metric_and_histogram_aggregations() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+    engine
+        .put_mapping(serde_json::from_value(json!({
+            "properties": { "shop": { "type": "keyword" } }
+        }))?)
+        .await?;
+
+    let orders = [
+        ("o1", "north", 10.0, "2024-01-15T08:00:00Z"),
+        ("o2", "north", 25.0, "2024-01-20T12:00:00Z"),
+        ("o3", "south", 40.0, "2024-03-02T09:30:00Z"),
+        ("o4", "south", 45.0, "2024-03-28T18:00:00Z"),
+        ("o5", "north", 100.0, "2024-04-01T00:00:00Z"),
+    ];
+    for (id, shop, amount, placed) in orders {
+        let mut doc = create_test_document(id, "customer order");
+        doc.metadata.insert("shop".to_string(), json!(shop));
+        doc.metadata.insert("amount".to_string(), json!(amount));
+        doc.metadata.insert("placed".to_string(), json!(placed));
+        engine.add_document(doc).await?;
+    }
+
+    let options = SearchOptions {
+        size: 0,
+        aggs: serde_json::from_value(json!({
+            "amounts": { "stats": { "field": "amount" } },
+            "lowest": { "min": { "field": "amount" } },
+            "average": { "avg": { "field": "amount" } },
+            "revenue": { "sum": { "field": "amount" } },
+            "latest": { "max": { "field": "placed" } },
+            "spread": { "percentiles": { "field": "amount", "percents": [50, 100] } },
+            "shops": { "cardinality": { "field": "shop" } },
+            "price_bands": { "histogram": { "field": "amount", "interval": 25 } },
+            "per_month": {
+                "date_histogram": { "field": "placed", "calendar_interval": "month" },
+                "aggs": { "revenue": { "sum": { "field": "amount" } } }
+            },
+            "per_shop": {
+                "terms": { "field": "shop" },
+                "aggs": {
+                    "average": { "avg": { "field": "amount" } },
+                    "weekly": { "date_histogram": { "field": "placed", "fixed_interval": "7d", "min_doc_count": 1 } }
+                }
+            }
+        }))?,
+        ..Default::default()
+    };
+    let results = engine.search_with_options("order", &options).await?;
+    assert_eq!(results.total, 5);
+    assert!(results.hits.is_empty());
+    let aggregations = serde_json::to_value(&results.aggregations)?;
+
+    assert_eq!(
+        aggregations["amounts"],
+        json!({ "count": 5, "min": 10.0, "max": 100.0, "avg": 44.0, "sum": 220.0 })
+    );
+    assert_eq!(aggregations["lowest"]["value"], 10.0);
+    assert_eq!(aggregations["average"]["value"], 44.0);
+    assert_eq!(aggregations["revenue"]["value"], 220.0);
+    assert_eq!(
+        aggregations["latest"]["value_as_string"],
+        "2024-04-01T00:00:00Z"
+    );
+    assert_eq!(
+        aggregations["spread"]["values"],
+        json!({ "50.0": 40.0, "100.0": 100.0 })
+    );
+    assert_eq!(aggregations["shops"]["value"], 2);
+
+    let bands: Vec<_> = aggregations["price_bands"]["buckets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|bucket| {
+            (
+                bucket["key"].as_f64().unwrap(),
+                bucket["doc_count"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        bands,
+        vec![(0.0, 1), (25.0, 3), (50.0, 0), (75.0, 0), (100.0, 1)]
+    );
+
+    let months = aggregations["per_month"]["buckets"].as_array().unwrap();
+    let months: Vec<_> = months
+        .iter()
+        .map(|bucket| {
+            (
+                bucket["key_as_string"].as_str().unwrap().to_string(),
+                bucket["doc_count"].as_u64().unwrap(),
+                bucket["revenue"]["value"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        months,
+        vec![
+            ("2024-01-01T00:00:00Z".to_string(), 2, 35.0),
+            ("2024-02-01T00:00:00Z".to_string(), 0, 0.0),
+            ("2024-03-01T00:00:00Z".to_string(), 2, 85.0),
+            ("2024-04-01T00:00:00Z".to_string(), 1, 100.0),
+        ]
+    );
+
+    let north = &aggregations["per_shop"]["buckets"][0];
+    assert_eq!(north["key"], "north");
+    assert_eq!(north["doc_count"], 3);
+    assert_eq!(north["average"]["value"], 45.0);
+    assert_eq!(north["weekly"]["buckets"].as_array().unwrap().len(), 3);
+
+    let invalid = [
+        json!({ "bad": { "avg": { "field": "shop" } } }),
+        json!({ "bad": { "histogram": { "field": "amount", "interval": 0 } } }),
+        json!({ "bad": { "date_histogram": { "field": "placed", "calendar_interval": "fortnight" } } }),
+        json!({ "bad": { "max": { "field": "amount" }, "aggs": { "inner": { "min": { "field": "amount" } } } } }),
+    ];
+    for aggs in invalid {
+        let options = SearchOptions {
+            aggs: serde_json::from_value(aggs)?,
+            ..Default::default()
+        };
+        assert!(engine.search_with_options("order", &options).await.is_err());
+    }
+
+    let mut doc = create_test_document("o6", "customer order");
+    doc.metadata
+        .insert("placed".to_string(), json!(1_000_000_000_000_000i64));
+    assert!(engine.add_document(doc).await.is_err());
+    let mut doc = create_test_document("o7", "customer order");
+    doc.metadata
+        .insert("shipped".to_string(), json!(1_000_000_000_000_000i64));
+    engine.add_document(doc).await?;
+    assert!(engine
+        .put_mapping(serde_json::from_value(json!({
+            "properties": { "shipped": { "type": "date" } }
+        }))?)
+        .await
+        .is_err());
+    let results = engine.search_with_options("order", &options).await?;
+    let aggregations = serde_json::to_value(&results.aggregations)?;
+    assert_eq!(
+        aggregations["per_month"]["buckets"]
+            .as_array()
+            .unwrap()
+            .len(),
+        4
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_approximate_aggregations() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let body: Vec<String> = (0..2000)
+        .flat_map(|i| {
+            [
+                json!({ "index": { "_id": format!("a{}", i) } }).to_string(),
+                json!({
+                    "content": "sensor reading",
+                    "metadata": { "reading": i, "sensor": format!("s{}", i % 500) }
+                })
+                .to_string(),
+            ]
+        })
+        .collect();
+    engine.bulk(parse_ndjson(&body.join("\n"))?).await?;
+
+    let options = SearchOptions {
+        size: 0,
+        aggs: serde_json::from_value(json!({
+            "spread": { "percentiles": { "field": "reading", "percents": [0, 50, 99, 100] } },
+            "sensors": { "cardinality": { "field": "sensor" } }
+        }))?,
+        ..Default::default()
+    };
+    let results = engine.search_with_options("reading", &options).await?;
+    assert_eq!(results.total, 2000);
+    let aggregations = serde_json::to_value(&results.aggregations)?;
+
+    let spread = &aggregations["spread"]["values"];
+    assert_eq!(spread["0.0"], 0.0);
+    assert_eq!(spread["100.0"], 1999.0);
+    assert!((spread["50.0"].as_f64().unwrap() - 999.5).abs() < 20.0);
+    assert!((spread["99.0"].as_f64().unwrap() - 1979.0).abs() < 5.0);
+    let sensors = aggregations["sensors"]["value"].as_u64().unwrap();
+    assert!((475..=525).contains(&sensors));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_facet_navigation() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+    engine
+        .put_mapping(serde_json::from_value(json!({
+            "properties": { "category": { "type": "facet" } }
+        }))?)
+        .await?;
+
+    let products = [
+        ("f1", json!("/electronics/phones/android")),
+        ("f2", json!("/electronics/phones/android")),
+        ("f3", json!("/electronics/phones/ios")),
+        ("f4", json!("/electronics/laptops")),
+        (
+            "f5",
+            json!(["/books/fiction", "/electronics/phones/accessories"]),
+        ),
+    ];
+    for (id, category) in products {
+        let mut doc = create_test_document(id, "catalog item");
+        doc.metadata.insert("category".to_string(), category);
+        engine.add_document(doc).await?;
+    }
+
+    let mut invalid = create_test_document("f6", "catalog item");
+    invalid
+        .metadata
+        .insert("category".to_string(), json!("electronics"));
+    assert!(engine.add_document(invalid).await.is_err());
+
+    let drill_down = |query: &'static str, path: &'static str| {
+        let engine = engine.clone();
+        async move {
+            let options = SearchOptions {
+                aggs: serde_json::from_value(json!({
+                    "levels": { "facet": { "field": "category", "path": path } }
+                }))?,
+                ..Default::default()
+            };
+            let results = engine.search_with_options(query, &options).await?;
+            let levels = serde_json::to_value(&results.aggregations["levels"])?;
+            let buckets: Vec<(String, u64)> = levels["buckets"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|bucket| {
+                    (
+                        bucket["key"].as_str().unwrap().to_string(),
+                        bucket["doc_count"].as_u64().unwrap(),
+                    )
+                })
+                .collect();
+            anyhow::Ok((results.total, buckets))
+        }
+    };
+
+    let (total, root) = drill_down("catalog", "/").await?;
+    assert_eq!(total, 5);
+    assert_eq!(
+        root,
+        vec![("/electronics".to_string(), 5), ("/books".to_string(), 1)]
+    );
+
+    let (total, phones) = drill_down(
+        "catalog AND category:/electronics/phones",
+        "/electronics/phones",
+    )
+    .await?;
+    assert_eq!(total, 4);
+    assert_eq!(
+        phones,
+        vec![
+            ("/electronics/phones/android".to_string(), 2),
+            ("/electronics/phones/accessories".to_string(), 1),
+            ("/electronics/phones/ios".to_string(), 1),
+        ]
+    );
+
+    let (total, leaves) = drill_down(
+        "category:/electronics/phones/ios",
+        "/electronics/phones/ios",
+    )
+    .await?;
+    assert_eq!(total, 1);
+    assert!(leaves.is_empty());
+
+    let query: QueryDsl = serde_json::from_value(json!({
+        "bool": { "filter": { "term": { "category": "/books" } } }
+    }))?;
+    let results = engine.search_dsl(&query, &SearchOptions::default()).await?;
+    assert_eq!(results.total, 1);
+    assert_eq!(results.hits[0].document.id, "f5");
+
+    let options = SearchOptions {
+        aggs: serde_json::from_value(json!({
+            "bad": { "facet": { "field": "author", "path": "/" } }
+        }))?,
+        ..Default::default()
+    };
+    assert!(engine
+        .search_with_options("catalog", &options)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fuzzy_matching() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    for (id, content) in [
+        ("z1", "distributed search engine"),
+        ("z2", "searching across clusters"),
+        ("z3", "research papers"),
+    ] {
+        engine
+            .add_document(create_test_document(id, content))
+            .await?;
+    }
+
+    let ids = |query: &'static str, prefix_length: usize| {
+        let engine = engine.clone();
+        async move {
+            let options = SearchOptions {
+                fuzzy_prefix_length: prefix_length,
+                ..Default::default()
+            };
+            let mut ids: Vec<String> = engine
+                .search_with_options(query, &options)
+                .await?
+                .hits
+                .into_iter()
+                .map(|hit| hit.document.id)
+                .collect();
+            ids.sort();
+            anyhow::Ok(ids)
+        }
+    };
+
+    assert!(ids("serch", 0).await?.is_empty());
+    assert_eq!(ids("serch~1", 0).await?, vec!["z1", "z2"]);
+    assert_eq!(ids("serch~", 0).await?, vec!["z1", "z2"]);
+    assert_eq!(ids("egnine~1 AND clusters", 0).await?, Vec::<String>::new());
+    assert_eq!(ids("egnine~1 OR clusters", 0).await?, vec!["z1", "z2"]);
+    assert_eq!(ids("content:papres~1", 0).await?, vec!["z3"]);
+    assert_eq!(ids("author:autor~1", 0).await?.len(), 3);
+    assert_eq!(ids("earch~1", 0).await?, vec!["z1", "z2"]);
+    assert!(ids("earch~1", 1).await?.is_empty());
+    assert_eq!(ids("sarch~1", 1).await?, vec!["z1", "z2"]);
+    assert_eq!(ids("+serch~1 -clusters", 0).await?, vec!["z1"]);
+    assert_eq!(ids("(papres~1 OR clusters)", 0).await?, vec!["z2", "z3"]);
+    assert_eq!(
+        ids("(egnine~1 OR papres~1) AND distributed", 0).await?,
+        vec!["z1"]
+    );
+    assert_eq!(ids("serch~1 NOT clusters", 0).await?, vec!["z1"]);
+    assert_eq!(ids("serch~1 AND NOT clusters", 0).await?, vec!["z1"]);
+    assert_eq!(ids("NOT clusters AND serch~1", 0).await?, vec!["z1"]);
+    assert_eq!(ids("searching AND NOT egnine~1", 0).await?, vec!["z2"]);
+    assert!(ids("zzfuzzyzz0", 0).await?.is_empty());
+    assert_eq!(ids("zzfuzzyzz0 serch~1", 0).await?, vec!["z1", "z2"]);
+
+    let dsl = |fuzziness: serde_json::Value| -> anyhow::Result<QueryDsl> {
+        Ok(serde_json::from_value(json!({
+            "match": {
+                "content": { "query": "serch egnine", "operator": "and", "fuzziness": fuzziness }
+            }
+        }))?)
+    };
+    let results = engine
+        .search_dsl(&dsl(json!("auto"))?, &SearchOptions::default())
+        .await?;
+    assert_eq!(results.total, 1);
+    assert_eq!(results.hits[0].document.id, "z1");
+    let results = engine
+        .search_dsl(&dsl(json!(0))?, &SearchOptions::default())
+        .await?;
+    assert_eq!(results.total, 0);
+    assert!(engine
+        .search_dsl(&dsl(json!(3))?, &SearchOptions::default())
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_suggest_completions() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    for (id, content, category) in [
+        ("s1", "distributed search engine", "databases"),
+        ("s2", "search relevance tuning", "databases"),
+        ("s3", "searching for meaning", "philosophy"),
+        ("s4", "seafood recipes", "cooking"),
+    ] {
+        let mut doc = create_test_document(id, content);
+        doc.metadata.insert("category".to_string(), json!(category));
+        engine.add_document(doc).await?;
+    }
+
+    let suggest = |prefix: &str, category: Option<&str>| {
+        let engine = engine.clone();
+        let request = SuggestRequest {
+            prefix: prefix.to_string(),
+            category: category.map(String::from),
+            ..Default::default()
+        };
+        async move {
+            let suggestions = engine.suggest(&request).await?;
+            anyhow::Ok(
+                suggestions
+                    .into_iter()
+                    .map(|suggestion| (suggestion.text, suggestion.doc_count))
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+
+    assert_eq!(
+        suggest("Sea", None).await?,
+        vec![
+            ("search".to_string(), 2),
+            ("seafood".to_string(), 1),
+            ("searching".to_string(), 1),
+        ]
+    );
+    assert_eq!(
+        suggest("sea", Some("philosophy")).await?,
+        vec![("searching".to_string(), 1)]
+    );
+    assert_eq!(
+        suggest("distributed sea", None).await?,
+        vec![("distributed search".to_string(), 1)]
+    );
+    assert!(suggest("xyz", None).await?.is_empty());
+
+    engine.delete_document("s4").await?;
+    assert_eq!(suggest("seaf", None).await?, Vec::new());
+
+    let by_author = SuggestRequest {
+        prefix: "aut".to_string(),
+        fields: vec!["author".to_string()],
+        ..Default::default()
+    };
+    let suggestions = engine.suggest(&by_author).await?;
+    assert_eq!(suggestions[0].text, "author");
+    assert_eq!(suggestions[0].doc_count, 3);
+
+    for invalid in [
+        SuggestRequest::default(),
+        SuggestRequest {
+            prefix: "sea".to_string(),
+            fields: vec!["missing".to_string()],
+            ..Default::default()
+        },
+    ] {
+        let err = engine.suggest(&invalid).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineError>(),
+            Some(EngineError::InvalidQuery(_))
+        ));
+    }
+
+    let mut config = create_test_config();
+    config.indexing.analyzer = "russian".to_string();
+    let engine = SearchEngine::new(&config)?;
+    engine
+        .add_document(create_test_document(
+            "ru1",
+            "Изучение языков программирования",
+        ))
+        .await?;
+    let request = SuggestRequest {
+        prefix: "Изучение прог".to_string(),
+        ..Default::default()
+    };
+    let suggestions = engine.suggest(&request).await?;
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!(suggestions[0].text, "Изучение программирования");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spelling_suggestions() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    for (id, content) in [
+        ("d1", "distributed search engine"),
+        ("d2", "search engine optimization"),
+        ("d3", "seared salmon"),
+    ] {
+        engine
+            .add_document(create_test_document(id, content))
+            .await?;
+    }
+
+    let suggest = SearchOptions {
+        suggest: true,
+        ..Default::default()
+    };
+
+    let results = engine.search_with_options("serch engne", &suggest).await?;
+    assert_eq!(results.total, 0);
+    assert_eq!(results.suggestions.len(), 1);
+    assert_eq!(results.suggestions[0].text, "search engine");
+    assert_eq!(results.suggestions[0].hits, 2);
+
+    let results = engine
+        .search_with_options("distributed AND serch", &suggest)
+        .await?;
+    assert_eq!(results.suggestions[0].text, "distributed AND search");
+    assert_eq!(results.suggestions[0].hits, 1);
+
+    let results = engine.search_with_options("author:autor", &suggest).await?;
+    assert_eq!(results.suggestions[0].text, "author:author");
+    assert_eq!(results.suggestions[0].hits, 3);
+
+    let results = engine.search_with_options("search", &suggest).await?;
+    assert_eq!(results.total, 2);
+    assert!(results.suggestions.is_empty());
+
+    assert!(engine
+        .search_with_options("xqzzyv", &suggest)
+        .await?
+        .suggestions
+        .is_empty());
+    assert!(engine
+        .search_with_options("serch", &SearchOptions::default())
+        .await?
+        .suggestions
+        .is_empty());
+
+    let mut config = create_test_config();
+    config.indexing.analyzer = "russian".to_string();
+    let engine = SearchEngine::new(&config)?;
+    engine
+        .add_document(create_test_document(
+            "ru1",
+            "Изучение языков программирования",
+        ))
+        .await?;
+    let results = engine
+        .search_with_options("програмирования", &suggest)
+        .await?;
+    assert_eq!(results.total, 0);
+    assert_eq!(results.suggestions[0].text, "программирования");
+    assert_eq!(results.suggestions[0].hits, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_phrase_slop() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    for (id, content) in [
+        ("p1", "язык программирования rust"),
+        ("p2", "программирования язык"),
+        ("p3", "программирования новый язык"),
+    ] {
+        engine
+            .add_document(create_test_document(id, content))
+            .await?;
+    }
+
+    let ids = |query: &'static str| {
+        let engine = engine.clone();
+        async move {
+            let mut ids: Vec<String> = engine
+                .search(query)
+                .await?
+                .into_iter()
+                .map(|hit| hit.document.id)
+                .collect();
+            ids.sort();
+            anyhow::Ok(ids)
+        }
+    };
+
+    assert_eq!(ids("программирования язык").await?, vec!["p1", "p2", "p3"]);
+    assert_eq!(ids("\"программирования язык\"").await?, vec!["p2"]);
+    assert_eq!(ids("\"программирования язык\"~1").await?, vec!["p2", "p3"]);
+    assert_eq!(
+        ids("content:\"программирования язык\"~1").await?,
+        vec!["p2", "p3"]
+    );
+    assert_eq!(
+        ids("\"программирования язык\" OR rusty~1").await?,
+        vec!["p1", "p2"]
+    );
+
+    for (slop, expected) in [(0, vec!["p2"]), (1, vec!["p2", "p3"])] {
+        let query: QueryDsl = serde_json::from_value(json!({
+            "match_phrase": { "content": { "query": "программирования язык", "slop": slop } }
+        }))?;
+        let mut ids: Vec<String> = engine
+            .search_dsl(&query, &SearchOptions::default())
+            .await?
+            .hits
+            .into_iter()
+            .map(|hit| hit.document.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, expected);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_russian_analysis() -> anyhow::Result<()> {
+    let mut config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    let mut doc = create_test_document("ru1", "Изучение языка программирования Rust");
+    doc.metadata
+        .insert("title".to_string(), json!("Ёжик в тумане"));
+    engine.add_document(doc).await?;
+    let mut doc = create_test_document("ru2", "Новый язык и старые книги");
+    doc.metadata
+        .insert("title".to_string(), json!("Ежи и лисы"));
+    engine.add_document(doc).await?;
+
+    assert_eq!(engine.search("content:языка").await?.len(), 1);
+    drop(engine);
+
+    config.indexing.analyzer = "russian".to_string();
+    config
+        .indexing
+        .field_analyzers
+        .insert("title".to_string(), "russian".to_string());
+    let engine = SearchEngine::new(&config)?;
+
+    let mapping = engine.mapping();
+    assert_eq!(mapping.analyzer, "russian");
+    assert_eq!(mapping.properties["title"].kind, FieldKind::Text);
+    assert_eq!(
+        mapping.properties["title"].analyzer.as_deref(),
+        Some("russian")
+    );
+
+    let ids = |query: &'static str| {
+        let engine = engine.clone();
+        async move {
+            let mut ids: Vec<String> = engine
+                .search(query)
+                .await?
+                .into_iter()
+                .map(|hit| hit.document.id)
+                .collect();
+            ids.sort();
+            anyhow::Ok(ids)
+        }
+    };
+
+    assert_eq!(ids("языка").await?, vec!["ru1", "ru2"]);
+    assert_eq!(ids("ЯЗЫКОВ").await?, vec!["ru1", "ru2"]);
+    assert_eq!(ids("\"языки программирования\"").await?, vec!["ru1"]);
+    assert_eq!(ids("title:ежик").await?, vec!["ru1"]);
+    assert_eq!(ids("title:ёжика").await?, vec!["ru1"]);
+    assert!(ids("и").await?.is_empty());
+    assert!(ids("title:в").await?.is_empty());
+
+    for (analyzer, field_analyzer) in [("klingon", "russian"), ("russian", "klingon")] {
+        let mut invalid = create_test_config();
+        invalid.indexing.analyzer = analyzer.to_string();
+        invalid
+            .indexing
+            .field_analyzers
+            .insert("title".to_string(), field_analyzer.to_string());
+        assert!(SearchEngine::new(&invalid).is_err());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multilingual_search() -> anyhow::Result<()> {
+    let config = create_test_config();
+    let engine = SearchEngine::new(&config)?;
+
+    assert_eq!(Language::detect("Привет, мир"), Some(Language::Ru));
+    assert_eq!(Language::detect("Язык Rust"), Some(Language::Ru));
+    assert_eq!(Language::detect("Hello world"), Some(Language::En));
+    assert_eq!(Language::detect("2024 — 42"), None);
+
+    engine
+        .add_document(create_test_document("ml1", "Rust — язык программирования"))
+        .await?;
+    engine
+        .add_document(create_test_document("ml2", "Rust programming language"))
+        .await?;
+    let mut doc = create_test_document("ml3", "programming tips");
+    doc.metadata.insert("lang".to_string(), json!("ru"));
+    engine.add_document(doc).await?;
+
+    let ids = |query: &'static str| {
+        let engine = engine.clone();
+        async move {
+            let ids: Vec<String> = engine
+                .search(query)
+                .await?
+                .into_iter()
+                .map(|hit| hit.document.id)
+                .collect();
+            anyhow::Ok(ids)
+        }
+    };
+
+    assert_eq!(ids("языков").await?, vec!["ml1"]);
+    assert_eq!(ids("programs").await?, vec!["ml2"]);
+    assert_eq!(ids("rust").await?, vec!["ml2", "ml1"]);
+    assert_eq!(ids("rust раст").await?, vec!["ml1", "ml2"]);
+    assert_eq!(ids("программированием~1").await?, vec!["ml1"]);
+
+    let query: QueryDsl = serde_json::from_value(json!({
+        "match": { "content": "языки" }
+    }))?;
+    let results = engine.search_dsl(&query, &SearchOptions::default()).await?;
+    assert_eq!(results.total, 1);
+    assert_eq!(results.hits[0].document.id, "ml1");
 
     Ok(())
 }